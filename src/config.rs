@@ -0,0 +1,305 @@
+//! Configuration for the serializer and deserializer.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+use error::Error;
+
+/// Options that control how a Serializer produces its output.
+///
+/// The default configuration emits standard MessagePack and fails on values it can't represent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SerializerConfig {
+    pub(crate) reject_non_finite: bool,
+    pub(crate) lossy: Option<fn(&Error)>,
+    pub(crate) backpatch_sequences: bool,
+    pub(crate) char_as_uint: bool,
+    pub(crate) unit_variant_as_uint: bool,
+    pub(crate) unit_variant_as_str: bool,
+    pub(crate) struct_as_array: bool,
+    pub(crate) legacy_raw: bool,
+    pub(crate) intern_strings: bool,
+    pub(crate) sort_map_keys: bool,
+    pub(crate) assume_sorted_keys: bool,
+    pub(crate) unit_struct_as_empty_map: bool,
+    pub(crate) skip_none_fields: bool,
+}
+
+impl SerializerConfig {
+    /// Create a new configuration with the default options.
+    pub fn new() -> SerializerConfig {
+        SerializerConfig::default()
+    }
+
+    /// Create a configuration that produces canonical output, where equal values always encode
+    /// to the same bytes, such as for signing.
+    ///
+    /// Canonical output follows these rules:
+    ///
+    /// * integers, and the lengths of strings, byte arrays, arrays and maps, use their smallest
+    ///   encoding
+    /// * floats keep the width of their type, and NaN and infinite floats are rejected
+    /// * map entries, including struct fields, are sorted by the bytes of their encoded keys, so
+    ///   shorter strings come before longer ones, and a map with two keys that encode the same
+    ///   fails with `Error::InvalidKey`
+    pub fn canonical() -> SerializerConfig {
+        SerializerConfig::new().reject_non_finite(true).sort_map_keys(true)
+    }
+
+    /// Fail with `Error::NonFinite` when serializing a NaN or infinite float.
+    pub fn reject_non_finite(mut self, reject: bool) -> SerializerConfig {
+        self.reject_non_finite = reject;
+        self
+    }
+
+    /// Emit nil for values that can't be represented instead of failing, such as non-finite
+    /// floats under `reject_non_finite`, 128-bit integers, or strings and byte arrays too long
+    /// for MessagePack. The observer is called with the error that was suppressed.
+    pub fn lossy(mut self, observer: fn(&Error)) -> SerializerConfig {
+        self.lossy = Some(observer);
+        self
+    }
+
+    /// Stream sequences of unknown length straight to the output behind a placeholder array32
+    /// header, and fill in the length once the sequence ends. This only applies to outputs that
+    /// can patch bytes they have already written; other outputs still buffer the sequence.
+    pub fn backpatch_sequences(mut self, backpatch: bool) -> SerializerConfig {
+        self.backpatch_sequences = backpatch;
+        self
+    }
+
+    /// Encode chars as their unicode scalar value instead of as a one-character string. The
+    /// deserializer accepts either form.
+    pub fn char_as_uint(mut self, as_uint: bool) -> SerializerConfig {
+        self.char_as_uint = as_uint;
+        self
+    }
+
+    /// Encode unit enum variants as just their variant index, rather than as the two-element
+    /// array of index and nil used for all other variants. The deserializer accepts either form.
+    pub fn unit_variant_as_uint(mut self, as_uint: bool) -> SerializerConfig {
+        self.unit_variant_as_uint = as_uint;
+        self
+    }
+
+    /// Encode unit enum variants as just their variant name, which suits enums used as map keys
+    /// that other implementations should be able to read. The deserializer accepts either form.
+    /// Takes precedence over `unit_variant_as_uint`.
+    pub fn unit_variant_as_str(mut self, as_str: bool) -> SerializerConfig {
+        self.unit_variant_as_str = as_str;
+        self
+    }
+
+    /// Encode structs as an array of their field values in declaration order, leaving out the
+    /// field names. The deserializer accepts either form, but the reader's struct must declare
+    /// the same fields in the same order.
+    pub fn struct_as_array(mut self, as_array: bool) -> SerializerConfig {
+        self.struct_as_array = as_array;
+        self
+    }
+
+    /// Encode both strings and byte arrays in the raw format from before MessagePack split them
+    /// into str and bin, for talking to implementations of the old spec. Raw values use the
+    /// fixstr, str16 and str32 type bytes, and never str8.
+    pub fn legacy_raw(mut self, legacy: bool) -> SerializerConfig {
+        self.legacy_raw = legacy;
+        self
+    }
+
+    /// Write repeated strings as a short reference to their first occurrence. This is a
+    /// non-standard extension that only a deserializer with `intern_strings` set can read; see
+    /// the `intern` module for the wire format.
+    pub fn intern_strings(mut self, intern: bool) -> SerializerConfig {
+        self.intern_strings = intern;
+        self
+    }
+
+    /// Write map entries, including struct fields, sorted by the bytes of their encoded keys, and
+    /// fail with `Error::InvalidKey` if two keys encode the same. Maps are buffered in full to
+    /// sort them.
+    pub fn sort_map_keys(mut self, sort: bool) -> SerializerConfig {
+        self.sort_map_keys = sort;
+        self
+    }
+
+    /// Under `sort_map_keys`, trust that maps already yield their entries in sorted order, and
+    /// write them as they come instead of buffering them. The order is still checked as each key
+    /// is written, failing with `Error::InvalidKey` on a key that is out of order or repeated.
+    ///
+    /// This suits a `BTreeMap` whose keys order the same way as their encodings, such as
+    /// non-negative integers or strings of equal length. Struct fields are still sorted.
+    pub fn assume_sorted_keys(mut self, assume: bool) -> SerializerConfig {
+        self.assume_sorted_keys = assume;
+        self
+    }
+
+    /// Encode unit structs as an empty map instead of nil. The deserializer accepts either form.
+    pub fn unit_struct_as_empty_map(mut self, as_map: bool) -> SerializerConfig {
+        self.unit_struct_as_empty_map = as_map;
+        self
+    }
+
+    /// Leave out struct fields whose value is None, so that they decode as missing. The map's
+    /// length isn't known until every field has been seen, so structs are written behind a
+    /// placeholder map32 header under `backpatch_sequences` on outputs that can patch it, and
+    /// buffered otherwise. Structs encoded with `struct_as_array` keep all of their fields.
+    pub fn skip_none_fields(mut self, skip: bool) -> SerializerConfig {
+        self.skip_none_fields = skip;
+        self
+    }
+}
+
+/// The longest extension payload a Deserializer accepts unless configured otherwise.
+pub const DEFAULT_MAX_EXT_LEN: usize = 1 << 20;
+
+/// Options that control how a Deserializer treats its input.
+///
+/// The default configuration places no limits on the input, other than capping extension payloads
+/// at `DEFAULT_MAX_EXT_LEN` bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeserializerConfig {
+    pub(crate) max_allocations: Option<usize>,
+    pub(crate) deny_duplicate_keys: bool,
+    pub(crate) intern_strings: bool,
+    pub(crate) single_value_as_seq: bool,
+    pub(crate) saturate_ints: bool,
+    pub(crate) tolerant_array_structs: bool,
+    pub(crate) max_ext_len: Option<usize>,
+    pub(crate) validate_all_strings: bool,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_collection_len: Option<usize>,
+    pub(crate) max_str_len: Option<usize>,
+    pub(crate) max_bin_len: Option<usize>,
+    pub(crate) max_total_alloc: Option<usize>,
+    pub(crate) little_endian_lengths: bool,
+}
+
+impl DeserializerConfig {
+    /// Create a new configuration with the default options.
+    pub fn new() -> DeserializerConfig {
+        DeserializerConfig::default()
+    }
+
+    /// Limit how many strings, byte arrays, extensions, arrays and maps a single decode may
+    /// produce. Exceeding the limit fails with `Error::TooBig`.
+    ///
+    /// These are the values that may need a heap allocation, and each one counts once whether or
+    /// not decoding it actually allocates: a string borrowed from the input counts, as does a map
+    /// however many allocations building it takes. So this bounds how much work a hostile input
+    /// can cause rather than the exact number of allocations; `max_total_alloc` bounds the bytes.
+    pub fn max_allocations(mut self, limit: usize) -> DeserializerConfig {
+        self.max_allocations = Some(limit);
+        self
+    }
+
+    /// Fail with `Error::DuplicateKey` if any map in the document contains the same key twice,
+    /// comparing integer and float keys by value and other keys by their encoded bytes. The error
+    /// carries the path to the offending map.
+    ///
+    /// Float keys follow IEEE equality, except that a NaN key fails with `Error::InvalidKey`
+    /// since it could never be looked up again. So 0.0 and -0.0 are the same key, as are a
+    /// float32 and a float64 holding the same number, but the integer 1 and the float 1.0 are
+    /// not.
+    pub fn deny_duplicate_keys(mut self, deny: bool) -> DeserializerConfig {
+        self.deny_duplicate_keys = deny;
+        self
+    }
+
+    /// Read input written with `SerializerConfig::intern_strings`, resolving string references
+    /// back to the strings they refer to. Resolved strings can't be borrowed from the input.
+    pub fn intern_strings(mut self, intern: bool) -> DeserializerConfig {
+        self.intern_strings = intern;
+        self
+    }
+
+    /// Accept a single value where a sequence is expected, decoding it as a sequence with one
+    /// element. This suits "one or many" fields, so a `Vec<String>` accepts a bare string as well
+    /// as an array of them. The lone element is decoded as if it were self-describing.
+    pub fn single_value_as_seq(mut self, single: bool) -> DeserializerConfig {
+        self.single_value_as_seq = single;
+        self
+    }
+
+    /// Accept structs written as arrays with a different number of elements than the struct has
+    /// fields, as happens when a positional schema gains or loses trailing fields. Elements past
+    /// the last field are skipped, and fields past the last element are treated as missing, so
+    /// they need `#[serde(default)]` on the field or the struct unless they are Options.
+    pub fn tolerant_array_structs(mut self, tolerant: bool) -> DeserializerConfig {
+        self.tolerant_array_structs = tolerant;
+        self
+    }
+
+    /// Clamp integers that don't fit in the type being decoded to its smallest or largest value,
+    /// instead of failing with `Error::Overflow`.
+    pub fn saturate_ints(mut self, saturate: bool) -> DeserializerConfig {
+        self.saturate_ints = saturate;
+        self
+    }
+
+    /// Limit the payload length of extensions, failing with `Error::TooBig` as soon as a longer
+    /// one is announced. Defaults to `DEFAULT_MAX_EXT_LEN`.
+    pub fn max_ext_len(mut self, limit: usize) -> DeserializerConfig {
+        self.max_ext_len = Some(limit);
+        self
+    }
+
+    /// Check that strings are valid UTF-8 even inside values that are being skipped, such as
+    /// unknown struct fields. By default the contents of skipped strings are not looked at.
+    pub fn validate_all_strings(mut self, validate: bool) -> DeserializerConfig {
+        self.validate_all_strings = validate;
+        self
+    }
+
+    /// Limit how deeply arrays, maps, options and enums may nest, failing with
+    /// `Error::DepthLimitExceeded` on the first value past the limit. A top-level scalar is at
+    /// depth 0, and the elements of a top-level array are at depth 1.
+    pub fn max_depth(mut self, limit: usize) -> DeserializerConfig {
+        self.max_depth = Some(limit);
+        self
+    }
+
+    /// Limit the number of elements in an array, or entries in a map, failing with
+    /// `Error::TooBig` as soon as a longer one is announced.
+    pub fn max_collection_len(mut self, limit: usize) -> DeserializerConfig {
+        self.max_collection_len = Some(limit);
+        self
+    }
+
+    /// Limit the length in bytes of strings, failing with `Error::TooBig` as soon as a longer one
+    /// is announced.
+    pub fn max_str_len(mut self, limit: usize) -> DeserializerConfig {
+        self.max_str_len = Some(limit);
+        self
+    }
+
+    /// Limit the length of byte arrays, failing with `Error::TooBig` as soon as a longer one is
+    /// announced.
+    pub fn max_bin_len(mut self, limit: usize) -> DeserializerConfig {
+        self.max_bin_len = Some(limit);
+        self
+    }
+
+    /// Limit the combined length of every string, byte array and extension payload in the input,
+    /// failing with `Error::TooBig` once the total passes the limit. This bounds the memory a
+    /// decode can use even when each value is within its own limit.
+    ///
+    /// Under `intern_strings`, the copy of each string kept for later references counts too, and
+    /// each reference counts the full length of the string it stands for.
+    pub fn max_total_alloc(mut self, limit: usize) -> DeserializerConfig {
+        self.max_total_alloc = Some(limit);
+        self
+    }
+
+    /// Read the two and four-byte lengths of strings, byte arrays, arrays, maps and extensions as
+    /// little-endian. This is not MessagePack: it only exists to read the output of producers that
+    /// get the byte order of lengths wrong, and misreads everything else. Integers and floats are
+    /// still read big-endian, and helpers that scan raw bytes, like `value_len`, ignore it.
+    pub fn little_endian_lengths(mut self, little_endian: bool) -> DeserializerConfig {
+        self.little_endian_lengths = little_endian;
+        self
+    }
+
+    pub(crate) fn max_ext_len_or_default(&self) -> usize {
+        self.max_ext_len.unwrap_or(DEFAULT_MAX_EXT_LEN)
+    }
+}
@@ -4,63 +4,407 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 #[cfg(feature = "alloc")]
-use alloc::Vec;
+use alloc::{Vec, String};
+
+use std::convert::TryFrom;
 
 use std::marker::PhantomData;
 
 use std::str;
 
-use byteorder::{ByteOrder, BigEndian};
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
 
 use serde::Deserialize;
 
 use serde;
 
 use seq_deserializer::*;
+use single_deserializer::*;
+use positional_deserializer::*;
 use ext_deserializer::*;
 use variant_deserializer::*;
 
+use config::DeserializerConfig;
 use defs::*;
 use error::Error;
+use intern;
+use raw;
 use read::{Read, Reference};
+#[cfg(feature = "std")]
+use read::{IoRead, IoBufRead};
+use timestamp;
+
+/// The integer types values are narrowed into, with the bounds to saturate to.
+pub(crate) trait Integer: TryFrom<u64> + TryFrom<i64> {
+    const MIN: Self;
+    const MAX: Self;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ident)*) => {
+        $(
+            impl Integer for $ty {
+                const MIN: $ty = $ty::MIN;
+                const MAX: $ty = $ty::MAX;
+            }
+        )*
+    }
+}
+
+impl_integer!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize);
+
+/// Implement the integer methods of `serde::Deserializer`, each of which narrows the value into
+/// its own type and hands it to the matching visitor method.
+macro_rules! deserialize_integers {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, Error>
+                where V: serde::de::Visitor<'de>
+            {
+                let ty = self.input(1)?[0];
+
+                match self.parse_integer(ty)? {
+                    Some(value) => visitor.$visit(value),
+                    None => self.parse_as(visitor, ty),
+                }
+            }
+        )*
+    }
+}
 
 /// The corepack Deserializer struct. Contains a closure that should produce
 /// the next slice of data of the given length
 pub struct Deserializer<'de, R: Read<'de>> {
     read: R,
     scratch: Vec<u8>,
+    config: DeserializerConfig,
+    heap_values: usize,
+    capture: Vec<u8>,
+    capturing: usize,
+    path: Vec<String>,
+    strings: Vec<String>,
+    skipping: usize,
+    depth: usize,
+    peak_depth: usize,
+    total_alloc: usize,
     phantom: PhantomData<&'de u8>,
 }
 
+#[cfg(feature = "std")]
+impl<'de, R: ::std::io::Read> Deserializer<'de, IoRead<R>> {
+    /// Create a new Deserializer that reads from an `std::io::Read` source, such as a file or
+    /// socket. Only the bytes of each value are read, so the source can hold more data after it.
+    pub fn from_read(reader: R) -> Deserializer<'de, IoRead<R>> {
+        Deserializer::new(IoRead::new(reader))
+    }
+
+    /// Create a new Deserializer that reads from an `std::io::Read` source, using the given
+    /// configuration.
+    pub fn from_read_with_config(reader: R, config: DeserializerConfig)
+                                 -> Deserializer<'de, IoRead<R>> {
+        Deserializer::with_config(IoRead::new(reader), config)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, R: ::std::io::BufRead> Deserializer<'de, IoBufRead<R>> {
+    /// Create a new Deserializer that reads from an `std::io::BufRead` source, such as a
+    /// `BufReader` around a file or socket.
+    ///
+    /// Strings and byte arrays that lie whole within the source's buffer are handed to the visitor
+    /// as transient slices without being copied, and the buffer is only refilled once it runs
+    /// dry. Only the bytes of each value are consumed, so the source can hold more data after it.
+    pub fn from_buf_read(reader: R) -> Deserializer<'de, IoBufRead<R>> {
+        Deserializer::new(IoBufRead::new(reader))
+    }
+
+    /// Create a new Deserializer that reads from an `std::io::BufRead` source, using the given
+    /// configuration.
+    pub fn from_buf_read_with_config(reader: R, config: DeserializerConfig)
+                                     -> Deserializer<'de, IoBufRead<R>> {
+        Deserializer::with_config(IoBufRead::new(reader), config)
+    }
+}
+
 impl<'de, R: Read<'de>> Deserializer<'de, R> {
     /// Create a new Deserializer given an input function.
     pub fn new(read: R) -> Deserializer<'de, R> {
+        Deserializer::with_config(read, DeserializerConfig::default())
+    }
+
+    /// Create a new Deserializer given an input function and a configuration.
+    pub fn with_config(read: R, config: DeserializerConfig) -> Deserializer<'de, R> {
         Deserializer {
-            read: read,
+            read,
             scratch: vec![],
+            config,
+            heap_values: 0,
+            capture: vec![],
+            capturing: 0,
+            path: vec![],
+            strings: vec![],
+            skipping: 0,
+            depth: 0,
+            peak_depth: 0,
+            total_alloc: 0,
             phantom: PhantomData,
         }
     }
 
+    /// Read the type byte of the next value.
+    pub(crate) fn read_type(&mut self) -> Result<u8, Error> {
+        Ok(self.input(1)?[0])
+    }
+
+    /// Read the next value as an extension, returning its type and payload without interpreting
+    /// them. Fails with `Error::BadType` if the next value isn't an extension.
+    ///
+    /// This lets a hand-written `Deserialize` implementation keep extensions it doesn't know
+    /// about, to forward them unchanged.
+    pub fn read_ext(&mut self) -> Result<(i8, Vec<u8>), Error> {
+        let ty = self.read_type()?;
+
+        self.count_heap_value(ty)?;
+
+        let size = match ty {
+            FIXEXT1 => 1,
+            FIXEXT2 => 2,
+            FIXEXT4 => 4,
+            FIXEXT8 => 8,
+            FIXEXT16 => 16,
+            EXT8 => self.input(1)?[0] as usize,
+            EXT16 => self.read_len16()?,
+            EXT32 => self.read_len32()?,
+            _ => return Err(Error::BadType),
+        };
+
+        self.check_ext_len(size)?;
+
+        let tag = read_signed(self.input(1)?[0]);
+        let payload = self.input(size)?.to_vec();
+
+        Ok((tag, payload))
+    }
+
+    /// The deepest nesting of arrays, maps, options and enums read so far. A top-level array of
+    /// scalars has a depth of 1.
+    pub fn peak_depth(&self) -> usize {
+        self.peak_depth
+    }
+
+    pub(crate) fn config(&self) -> &DeserializerConfig {
+        &self.config
+    }
+
+    /// Start recording the raw bytes that are read. Captures can nest; returns the start of this
+    /// capture to pass to end_capture.
+    pub(crate) fn start_capture(&mut self) -> usize {
+        self.capturing += 1;
+        self.capture.len()
+    }
+
+    /// Stop recording, returning the raw bytes read since the matching start_capture.
+    pub(crate) fn end_capture(&mut self, start: usize) -> Vec<u8> {
+        let captured = self.capture[start..].to_vec();
+
+        self.capturing -= 1;
+        if self.capturing == 0 {
+            self.capture.clear();
+        }
+
+        captured
+    }
+
+    /// Track that we're descending into a map value or array element, for error reporting.
+    pub(crate) fn push_path(&mut self, segment: String) {
+        self.path.push(segment);
+    }
+
+    pub(crate) fn pop_path(&mut self) {
+        self.path.pop();
+    }
+
+    /// The location of the value currently being decoded, like `/outer/inner/0`.
+    pub(crate) fn path(&self) -> String {
+        if self.path.is_empty() {
+            "/".into()
+        } else {
+            self.path.iter().fold(String::new(), |path, segment| path + "/" + segment)
+        }
+    }
+
+    /// Skip over the next value, handing the bytes it occupied to the visitor.
+    fn capture_raw<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+        where V: serde::de::Visitor<'de>
+    {
+        let start = self.start_capture();
+        let skipped = serde::de::IgnoredAny::deserialize(&mut *self);
+        let captured = self.end_capture(start);
+
+        skipped?;
+        visitor.visit_byte_buf(captured)
+    }
+
+    fn check_ext_len(&mut self, len: usize) -> Result<(), Error> {
+        let limit = self.config.max_ext_len_or_default();
+
+        self.check_payload_len(len, Some(limit))
+    }
+
+    /// Check the announced length of a string, byte array or extension payload against its own
+    /// limit and against the running total for the whole input.
+    fn check_payload_len(&mut self, len: usize, limit: Option<usize>) -> Result<(), Error> {
+        match limit {
+            Some(limit) if len > limit => return Err(Error::TooBig),
+            _ => {}
+        }
+
+        self.charge_total_alloc(len)
+    }
+
+    /// Add to the running total checked against `max_total_alloc`.
+    fn charge_total_alloc(&mut self, len: usize) -> Result<(), Error> {
+        self.total_alloc = self.total_alloc.saturating_add(len);
+
+        match self.config.max_total_alloc {
+            Some(limit) if self.total_alloc > limit => Err(Error::TooBig),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a closure one level deeper into the input, enforcing `max_depth`.
+    fn nested<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Self) -> Result<T, Error>
+    {
+        match self.config.max_depth {
+            Some(limit) if self.depth >= limit => return Err(Error::DepthLimitExceeded),
+            _ => {}
+        }
+
+        self.depth += 1;
+        self.peak_depth = self.peak_depth.max(self.depth);
+        let value = f(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn check_collection_len(&self, len: usize) -> Result<(), Error> {
+        match self.config.max_collection_len {
+            Some(limit) if len > limit => Err(Error::TooBig),
+            _ => Ok(()),
+        }
+    }
+
+    /// Visit an array or map with the given number of elements or entries.
+    fn parse_collection<V>(&mut self, len: usize, map: bool, visitor: V) -> Result<V::Value, Error>
+        where V: serde::de::Visitor<'de>
+    {
+        self.check_collection_len(len)?;
+
+        self.nested(|de| if map {
+                        visitor.visit_map(SeqDeserializer::new(de, len * 2))
+                    } else {
+                        visitor.visit_seq(SeqDeserializer::new(de, len))
+                    })
+    }
+
+    /// Count a value towards `max_allocations` if its type is one that may allocate.
+    fn count_heap_value(&mut self, ty: u8) -> Result<(), Error> {
+        if !is_heap_value(ty) {
+            return Ok(());
+        }
+
+        self.heap_values += 1;
+
+        match self.config.max_allocations {
+            Some(limit) if self.heap_values > limit => Err(Error::TooBig),
+            _ => Ok(()),
+        }
+    }
+
     #[inline]
     fn input<'a>(&'a mut self, len: usize) -> Result<Reference<'de, 'a>, Error> {
         let result = self.read.input(len, &mut self.scratch)?;
         debug_assert!(result.len() == len);
+
+        if self.capturing > 0 {
+            self.capture.extend_from_slice(&result);
+        }
+
         Ok(result)
     }
 
+    /// Read a two-byte length, which is big-endian unless `little_endian_lengths` is set.
+    fn read_len16(&mut self) -> Result<usize, Error> {
+        let little_endian = self.config.little_endian_lengths;
+        let bytes = self.input(U16_BYTES)?;
+
+        Ok(if little_endian {
+            LittleEndian::read_u16(&bytes)
+        } else {
+            BigEndian::read_u16(&bytes)
+        } as usize)
+    }
+
+    /// Read a four-byte length, which is big-endian unless `little_endian_lengths` is set.
+    fn read_len32(&mut self) -> Result<usize, Error> {
+        let little_endian = self.config.little_endian_lengths;
+        let bytes = self.input(U32_BYTES)?;
+
+        Ok(if little_endian {
+            LittleEndian::read_u32(&bytes)
+        } else {
+            BigEndian::read_u32(&bytes)
+        } as usize)
+    }
+
     #[inline]
-    fn parse_str<'a, V>(reference: Reference<'de, 'a>, visitor: V) -> Result<V::Value, Error>
+    fn parse_str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        match reference {
-            Reference::Borrowed(buf) => {
-                visitor.visit_borrowed_str(str::from_utf8(buf).map_err(|e| Into::<Error>::into(e))?)
-            }
-            Reference::Copied(buf) => {
-                visitor.visit_str(str::from_utf8(buf).map_err(|e| Into::<Error>::into(e))?)
-            }
+        let limit = self.config.max_str_len;
+        self.check_payload_len(len, limit)?;
+
+        let interning = self.config.intern_strings && len >= intern::MIN_INTERNED_LEN;
+
+        // a skipped string is only looked at if it has to be checked or numbered
+        if self.skipping > 0 && !interning && !self.config.validate_all_strings {
+            return match self.input(len)? {
+                Reference::Borrowed(buf) => visitor.visit_borrowed_bytes(buf),
+                Reference::Copied(buf) => visitor.visit_bytes(buf),
+            };
+        }
+
+        let (value, interned) = {
+            let (value, string) = match self.input(len)? {
+                Reference::Borrowed(buf) => {
+                    let string = str::from_utf8(buf)?;
+                    (visitor.visit_borrowed_str::<Error>(string), string)
+                }
+                Reference::Copied(buf) => {
+                    let string = str::from_utf8(buf)?;
+                    (visitor.visit_str::<Error>(string), string)
+                }
+            };
+
+            let interned: Option<String> = if interning {
+                Some(string.into())
+            } else {
+                None
+            };
+
+            (value?, interned)
+        };
+
+        // keep numbering strings the same way the serializer did
+        if let Some(interned) = interned {
+            // the table keeps its own copy for the rest of the input
+            self.charge_total_alloc(interned.len())?;
+            self.strings.push(interned);
         }
+
+        Ok(value)
     }
 
     #[inline]
@@ -73,42 +417,104 @@ impl<'de, R: Read<'de>> Deserializer<'de, R> {
         }
     }
 
-    fn parse_as<V>(&mut self, visitor: V, ty: u8) -> Result<V::Value, Error>
+    #[inline]
+    fn parse_ext<V>(&mut self, ty: i8, len: usize, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
+        self.check_ext_len(len)?;
+
+        if ty == intern::STRING_REF_TYPE && self.config.intern_strings {
+            let index = intern::decode_ref(&self.input(len)?)?;
+            let interned_len = self.strings.get(index).ok_or(Error::Ext)?.len();
+
+            // a few bytes of reference stand for the whole string
+            self.charge_total_alloc(interned_len)?;
+
+            return visitor.visit_str(&self.strings[index]);
+        }
+
+        let buf = self.input(len)?;
+
+        match timestamp::decode(ty, &buf) {
+            Some((seconds, nanoseconds)) => {
+                visitor.visit_map(TimestampDeserializer::new(seconds, nanoseconds))
+            }
+            None => visitor.visit_map(ExtDeserializer::new(ty, &buf)),
+        }
+    }
+
+    /// Read an integer value of the given type, checking that it fits in T, or clamping it to
+    /// T's range under `saturate_ints`. Returns None if the type isn't an integer.
+    pub(crate) fn parse_integer<T>(&mut self, ty: u8) -> Result<Option<T>, Error>
+        where T: Integer
+    {
+        let saturate = self.config.saturate_ints;
+
+        let unsigned = |value: u64| match <T as TryFrom<u64>>::try_from(value) {
+            Ok(value) => Ok(value),
+            Err(_) if saturate => Ok(T::MAX),
+            Err(_) => Err(Error::Overflow),
+        };
+        let signed = |value: i64| match <T as TryFrom<i64>>::try_from(value) {
+            Ok(value) => Ok(value),
+            Err(_) if saturate && value < 0 => Ok(T::MIN),
+            Err(_) if saturate => Ok(T::MAX),
+            Err(_) => Err(Error::Overflow),
+        };
+
+        match ty {
+            v if POS_FIXINT.contains(v) => unsigned(v as u64).map(Some),
+            v if NEG_FIXINT.contains(v) => signed(read_signed(v) as i64).map(Some),
+            UINT8 => unsigned(self.input(1)?[0] as u64).map(Some),
+            UINT16 => unsigned(BigEndian::read_u16(&self.input(U16_BYTES)?) as u64).map(Some),
+            UINT32 => unsigned(BigEndian::read_u32(&self.input(U32_BYTES)?) as u64).map(Some),
+            UINT64 => unsigned(BigEndian::read_u64(&self.input(U64_BYTES)?)).map(Some),
+            INT8 => signed(read_signed(self.input(1)?[0]) as i64).map(Some),
+            INT16 => signed(BigEndian::read_i16(&self.input(U16_BYTES)?) as i64).map(Some),
+            INT32 => signed(BigEndian::read_i32(&self.input(U32_BYTES)?) as i64).map(Some),
+            INT64 => signed(BigEndian::read_i64(&self.input(U64_BYTES)?)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn parse_as<V>(&mut self, visitor: V, ty: u8) -> Result<V::Value, Error>
+        where V: serde::de::Visitor<'de>
+    {
+        self.count_heap_value(ty)?;
+
         match ty {
             v if POS_FIXINT.contains(v) => visitor.visit_u8(v),
             v if NEG_FIXINT.contains(v) => visitor.visit_i8(read_signed(v)),
             v if FIXMAP.contains(v) => {
-                let size = (v & !FIXMAP_MASK) as usize * 2;
-                visitor.visit_map(SeqDeserializer::new(self, size))
+                self.parse_collection((v & !FIXMAP_MASK) as usize, true, visitor)
             }
             v if FIXARRAY.contains(v) => {
-                let size = (v & !FIXARRAY_MASK) as usize;
-                visitor.visit_seq(SeqDeserializer::new(self, size))
-            }
-            v if FIXSTR.contains(v) => {
-                let reference = self.input((v & !FIXSTR_MASK) as usize)?;
-
-                Deserializer::<'de, R>::parse_str(reference, visitor)
+                self.parse_collection((v & !FIXARRAY_MASK) as usize, false, visitor)
             }
+            v if FIXSTR.contains(v) => self.parse_str((v & !FIXSTR_MASK) as usize, visitor),
             NIL => visitor.visit_unit(),
             FALSE => visitor.visit_bool(false),
             TRUE => visitor.visit_bool(true),
             BIN8 => {
-                let size = self.input(1)?[0];
-                let reference = self.input(size as usize)?;
+                let size = self.input(1)?[0] as usize;
+                let limit = self.config.max_bin_len;
+                self.check_payload_len(size, limit)?;
+                let reference = self.input(size)?;
 
                 Deserializer::<'de, R>::parse_bytes(reference, visitor)
             }
             BIN16 => {
-                let size = BigEndian::read_u16(&self.input(U16_BYTES)?) as usize;
+                let size = self.read_len16()?;
+                let limit = self.config.max_bin_len;
+                self.check_payload_len(size, limit)?;
                 let reference = self.input(size)?;
 
                 Deserializer::<'de, R>::parse_bytes(reference, visitor)
             }
             BIN32 => {
-                let size = BigEndian::read_u32(&self.input(U32_BYTES)?) as usize;
+                let size = self.read_len32()?;
+                let limit = self.config.max_bin_len;
+                self.check_payload_len(size, limit)?;
                 let reference = self.input(size)?;
 
                 Deserializer::<'de, R>::parse_bytes(reference, visitor)
@@ -118,24 +524,21 @@ impl<'de, R: Read<'de>> Deserializer<'de, R> {
 
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(size)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, size, visitor)
             }
             EXT16 => {
-                let size = BigEndian::read_u16(&self.input(U16_BYTES)?) as usize;
+                let size = self.read_len16()?;
 
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(size)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, size, visitor)
             }
             EXT32 => {
-                let size = BigEndian::read_u32(&self.input(U32_BYTES)?) as usize;
+                let size = self.read_len32()?;
 
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(size)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, size, visitor)
             }
             UINT8 => {
                 let buf = self.input(1)?;
@@ -180,77 +583,69 @@ impl<'de, R: Read<'de>> Deserializer<'de, R> {
             FIXEXT1 => {
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(1)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, 1, visitor)
             }
             FIXEXT2 => {
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(2)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, 2, visitor)
             }
             FIXEXT4 => {
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(4)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, 4, visitor)
             }
             FIXEXT8 => {
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(8)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, 8, visitor)
             }
             FIXEXT16 => {
                 let ty: i8 = read_signed(self.input(1)?[0]);
 
-                let buf = self.input(16)?;
-                visitor.visit_map(ExtDeserializer::new(ty, &buf))
+                self.parse_ext(ty, 16, visitor)
             }
             STR8 => {
                 let size = self.input(1)?[0] as usize;
 
-                let buf = self.input(size)?;
-                Deserializer::<'de, R>::parse_str(buf, visitor)
+                self.parse_str(size, visitor)
             }
             STR16 => {
-                let size = BigEndian::read_u16(&self.input(U16_BYTES)?) as usize;
+                let size = self.read_len16()?;
 
-                let buf = self.input(size)?;
-                Deserializer::<'de, R>::parse_str(buf, visitor)
+                self.parse_str(size, visitor)
             }
             STR32 => {
-                let size = BigEndian::read_u16(&self.input(U32_BYTES)?) as usize;
+                let size = self.read_len32()?;
 
-                let buf = self.input(size)?;
-                Deserializer::<'de, R>::parse_str(buf, visitor)
+                self.parse_str(size, visitor)
             }
             ARRAY16 => {
-                let size = BigEndian::read_u16(&self.input(U16_BYTES)?);
+                let size = self.read_len16()?;
 
-                visitor.visit_seq(SeqDeserializer::new(self, size as usize))
+                self.parse_collection(size, false, visitor)
             }
             ARRAY32 => {
-                let size = BigEndian::read_u32(&self.input(U32_BYTES)?);
+                let size = self.read_len32()?;
 
-                visitor.visit_seq(SeqDeserializer::new(self, size as usize))
+                self.parse_collection(size, false, visitor)
             }
             MAP16 => {
-                let size = BigEndian::read_u16(&self.input(U16_BYTES)?);
+                let size = self.read_len16()?;
 
-                visitor.visit_map(SeqDeserializer::new(self, size as usize * 2))
+                self.parse_collection(size, true, visitor)
             }
             MAP32 => {
-                let size = BigEndian::read_u32(&self.input(U32_BYTES)?);
+                let size = self.read_len32()?;
 
-                visitor.visit_map(SeqDeserializer::new(self, size as usize * 2))
+                self.parse_collection(size, true, visitor)
             }
             _ => Err(Error::BadType),
         }
     }
 }
 
-impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'de, R> {
+impl<'de, R: Read<'de>> serde::Deserializer<'de> for &mut Deserializer<'de, R> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
@@ -264,55 +659,22 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_any(visitor)
-    }
-
-    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_any(visitor)
-    }
-
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_u64(visitor)
-    }
-
-    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_u64(visitor)
-    }
-
-    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_u64(visitor)
-    }
-
-    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_any(visitor)
-    }
-
-    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_i64(visitor)
-    }
-
-    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_i64(visitor)
+        match self.input(1)?[0] {
+            TRUE => visitor.visit_bool(true),
+            FALSE => visitor.visit_bool(false),
+            _ => Err(Error::BadType),
+        }
     }
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Error>
-        where V: serde::de::Visitor<'de>
-    {
-        self.deserialize_i64(visitor)
+    deserialize_integers! {
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Error>
@@ -336,7 +698,13 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_str(visitor)
+        let ty = self.input(1)?[0];
+
+        // chars may also be encoded as their scalar value
+        match self.parse_integer(ty)? {
+            Some(value) => visitor.visit_char(char::from_u32(value).ok_or(Error::BadType)?),
+            None => self.parse_as(visitor, ty),
+        }
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Error>
@@ -354,22 +722,42 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        // hack below
-        let (is_some /* maybe value */,) = Deserialize::deserialize(&mut *self)?;
+        // options are written as [false] or [true, value], but accept a bare nil for None too
+        let ty = self.read_type()?;
+        if ty == NIL {
+            return visitor.visit_none();
+        }
+
+        // check the header before reading any further, so a bad one fails without consuming more
+        if ty != 1 | FIXARRAY_MASK && ty != 2 | FIXARRAY_MASK {
+            return Err(Error::BadType);
+        }
 
-        if is_some {
+        self.count_heap_value(ty)?;
+
+        let is_some: bool = Deserialize::deserialize(&mut *self)?;
+
+        match (ty, is_some) {
             // This works because there are no terminating sequences for tuples or the like
-            visitor.visit_some(self)
-            // otherwise cleanup would be required here
-        } else {
-            visitor.visit_none()
+            (ty, true) if ty == 2 | FIXARRAY_MASK => self.nested(|de| visitor.visit_some(de)),
+            (ty, false) if ty == 1 | FIXARRAY_MASK => visitor.visit_none(),
+            _ => Err(Error::BadType),
         }
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_any(visitor)
+        let ty = self.input(1)?[0];
+
+        match ty {
+            v if FIXARRAY.contains(v) => self.parse_as(visitor, ty),
+            ARRAY16 | ARRAY32 => self.parse_as(visitor, ty),
+            _ if self.config.single_value_as_seq => {
+                visitor.visit_seq(SingleDeserializer::new(self, ty))
+            }
+            _ => self.parse_as(visitor, ty),
+        }
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Error>
@@ -393,13 +781,28 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
     fn deserialize_unit_struct<V>(self, _: &'static str, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_unit(visitor)
+        let ty = self.input(1)?[0];
+
+        // some peers write unit structs as an empty map rather than nil
+        if ty == FIXMAP_MASK {
+            visitor.visit_unit()
+        } else {
+            self.parse_as(visitor, ty)
+        }
     }
 
-    fn deserialize_newtype_struct<V>(self, _: &'static str, visitor: V) -> Result<V::Value, Error>
+    fn deserialize_newtype_struct<V>(self,
+                                     name: &'static str,
+                                     visitor: V)
+                                     -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_any(visitor)
+        if name == raw::RAW_MESSAGE_NAME {
+            return self.capture_raw(visitor);
+        }
+
+        // newtypes are written as just their inner value
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_tuple_struct<V>(self,
@@ -414,12 +817,29 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
 
     fn deserialize_struct<V>(self,
                              _: &'static str,
-                             _: &'static [&'static str],
+                             fields: &'static [&'static str],
                              visitor: V)
                              -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_map(visitor)
+        if !self.config.tolerant_array_structs {
+            return self.deserialize_map(visitor);
+        }
+
+        let ty = self.read_type()?;
+
+        let len = match ty {
+            v if FIXARRAY.contains(v) => (v & !FIXARRAY_MASK) as usize,
+            ARRAY16 => self.read_len16()?,
+            ARRAY32 => self.read_len32()?,
+            _ => return self.parse_as(visitor, ty),
+        };
+
+        self.count_heap_value(ty)?;
+        self.check_collection_len(len)?;
+
+        // present the elements as a map keyed by field name, so missing fields get their defaults
+        self.nested(|de| visitor.visit_map(PositionalDeserializer::new(de, fields, len)))
     }
 
     fn deserialize_tuple<V>(self, _: usize, visitor: V) -> Result<V::Value, Error>
@@ -435,25 +855,50 @@ impl<'de, 'a, R: Read<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'d
                            -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        visitor.visit_enum(VariantDeserializer::new(self, variants))
+        self.nested(|de| visitor.visit_enum(VariantDeserializer::new(de, variants)))
     }
 
     fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_any(visitor)
+        self.skipping += 1;
+        let value = self.deserialize_any(visitor);
+        self.skipping -= 1;
+
+        value
     }
 
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Error>
         where V: serde::de::Visitor<'de>
     {
-        self.deserialize_any(visitor)
+        let ty = self.read_type()?;
+
+        // a variant name on its own, like the tag of an adjacently tagged enum, is written as a
+        // unit variant: [index, nil]
+        if ty == 2 | FIXARRAY_MASK {
+            let index_ty = self.read_type()?;
+            let index = self.parse_integer::<u64>(index_ty)?.ok_or(Error::BadType)?;
+
+            if self.read_type()? != NIL {
+                return Err(Error::BadType);
+            }
+
+            return visitor.visit_u64(index);
+        }
+
+        self.parse_as(visitor, ty)
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::BTreeMap;
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+
+    use config::DeserializerConfig;
+    use error::Error;
 
     #[test]
     fn positive_fixint_test() {
@@ -498,6 +943,286 @@ mod test {
         assert_eq!(value, s);
     }
 
+    #[test]
+    fn option_header_test() {
+        // a bare true is refused from its header alone, without reading on for a flag
+        match ::from_bytes::<Option<u8>>(&[0xc3]) {
+            Err(Error::BadType) => {}
+            other => panic!("expected BadType, got {:?}", other),
+        }
+
+        match ::from_read::<_, Option<u8>>(&[0xc3][..]) {
+            Err(Error::BadType) => {}
+            other => panic!("expected BadType, got {:?}", other),
+        }
+
+        assert_eq!(::from_bytes::<Option<u8>>(&[0x92, 0xc3, 0x07]).unwrap(), Some(7));
+        assert_eq!(::from_bytes::<Option<u8>>(&[0x91, 0xc2]).unwrap(), None);
+    }
+
+    #[test]
+    fn borrowed_option_test() {
+        #[derive(Deserialize)]
+        struct Named<'a> {
+            #[serde(borrow)]
+            name: Option<&'a str>,
+        }
+
+        let fixture = [0x81, 0xa4, 0x6e, 0x61, 0x6d, 0x65, 0x92, 0xc3, 0xa2, 0x68, 0x69];
+        let named: Named = ::from_bytes(&fixture).unwrap();
+        assert_eq!(named.name, Some("hi"));
+
+        let value: Option<&str> = ::from_bytes(&[0x91, 0xc2]).unwrap();
+        assert_eq!(value, None);
+
+        let value: Option<&str> = ::from_bytes(&[0xc0]).unwrap();
+        assert_eq!(value, None);
+
+        assert!(::from_bytes::<Option<&str>>(&[0x91, 0xc3, 0xa2, 0x68, 0x69]).is_err());
+    }
+
+    #[test]
+    fn single_value_as_seq_test() {
+        let config = DeserializerConfig::new().single_value_as_seq(true);
+
+        let value: Vec<String> = ::from_bytes_with_config(&[0xa1, 0x61], config).unwrap();
+        assert_eq!(value, vec!["a"]);
+
+        let value: Vec<String> = ::from_bytes_with_config(&[0x92, 0xa1, 0x61, 0xa1, 0x62], config)
+            .unwrap();
+        assert_eq!(value, vec!["a", "b"]);
+
+        assert!(::from_bytes::<Vec<String>>(&[0xa1, 0x61]).is_err());
+    }
+
+    #[test]
+    fn read_ext_test() {
+        use read::BorrowRead;
+        use Deserializer;
+
+        let fixture = [0xc7, 0x03, 0x2a, 0x01, 0x02, 0x03, 0xd4, 0xff, 0x09, 0xa1, 0x61];
+        let mut position = 0;
+
+        let mut de = Deserializer::new(BorrowRead::new(|len: usize| {
+            let result = &fixture[position..position + len];
+            position += len;
+            Ok(result)
+        }));
+
+        assert_eq!(de.read_ext().unwrap(), (42, vec![1, 2, 3]));
+        assert_eq!(de.read_ext().unwrap(), (-1, vec![9]));
+
+        match de.read_ext() {
+            Err(Error::BadType) => {}
+            other => panic!("expected BadType, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn saturate_ints_test() {
+        let config = DeserializerConfig::new().saturate_ints(true);
+
+        assert_eq!(::from_bytes_with_config::<u8>(&[0xcd, 0x01, 0x2c], config).unwrap(), 255);
+        assert_eq!(::from_bytes_with_config::<u8>(&[0xfb], config).unwrap(), 0);
+        assert_eq!(::from_bytes_with_config::<i8>(&[0xd1, 0xfc, 0x18], config).unwrap(), -128);
+        assert_eq!(::from_bytes_with_config::<i64>(&[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                                     0xff, 0xff],
+                                                   config)
+                       .unwrap(),
+                   i64::MAX);
+
+        assert!(::from_bytes::<u8>(&[0xcd, 0x01, 0x2c]).is_err());
+        assert!(::from_bytes::<u8>(&[0xfb]).is_err());
+    }
+
+    #[test]
+    fn max_ext_len_test() {
+        // an ext32 claiming a 4 GiB payload fails on the header, not on running out of input
+        match ::from_bytes::<BTreeMap<String, i64>>(&[0xc9, 0xff, 0xff, 0xff, 0xff, 0x01]) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+
+        let config = DeserializerConfig::new().max_ext_len(2);
+        let fixture = [0xd6, 0x01, 0x00, 0x00, 0x00, 0x00];
+        assert!(::from_bytes_with_config::<BTreeMap<String, i64>>(&fixture, config).is_err());
+
+        let config = DeserializerConfig::new().max_ext_len(4);
+        assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(&fixture, config).is_ok());
+    }
+
+    #[test]
+    fn validate_all_strings_test() {
+        #[derive(Deserialize, Debug)]
+        struct Known {
+            #[allow(dead_code)]
+            id: u8,
+        }
+
+        // {"id": 1, "junk": ["\xff\xfe"]}
+        let fixture = [0x82, 0xa2, 0x69, 0x64, 0x01, 0xa4, 0x6a, 0x75, 0x6e, 0x6b, 0x91, 0xa2,
+                       0xff, 0xfe];
+
+        assert!(::from_bytes::<Known>(&fixture).is_ok());
+
+        let config = DeserializerConfig::new().validate_all_strings(true);
+        match ::from_bytes_with_config::<Known>(&fixture, config) {
+            Err(Error::Utf8Error(_)) => {}
+            other => panic!("expected Utf8Error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hardened_config_test() {
+        let hardened = DeserializerConfig::new()
+            .max_depth(4)
+            .max_collection_len(8)
+            .max_str_len(16)
+            .max_bin_len(16)
+            .max_total_alloc(64)
+            .deny_duplicate_keys(true);
+        let permissive = DeserializerConfig::new();
+
+        let accepts = |bytes: &[u8]| {
+            assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(bytes, permissive)
+                        .is_ok());
+        };
+        let rejects = |bytes: &[u8], expected: Error| {
+            let result = ::from_bytes_with_config::<::serde::de::IgnoredAny>(bytes, hardened);
+            assert_eq!(result.map(|_| ()).unwrap_err().to_string(), expected.to_string());
+        };
+
+        // [[[[[]]]]]
+        let deep = [0x91, 0x91, 0x91, 0x91, 0x90];
+        accepts(&deep);
+        rejects(&deep, Error::DepthLimitExceeded);
+        assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(&deep[1..], hardened).is_ok());
+
+        let long_array = ::to_bytes(vec![0u8; 9]).unwrap();
+        accepts(&long_array);
+        rejects(&long_array, Error::TooBig);
+
+        let long_str = ::to_bytes("seventeen bytes!!").unwrap();
+        accepts(&long_str);
+        rejects(&long_str, Error::TooBig);
+
+        let long_bin = [0xc4, 0x11];
+        rejects(&long_bin, Error::TooBig);
+
+        // eight strings of ten bytes each are fine one at a time, but not all together
+        let many_strs = ::to_bytes(vec!["0123456789"; 8]).unwrap();
+        accepts(&many_strs);
+        rejects(&many_strs, Error::TooBig);
+
+        // Some(Some(Some(Some(Some(1)))))
+        let options = ::to_bytes(Some(Some(Some(Some(Some(1u8)))))).unwrap();
+        assert!(::from_bytes::<Option<Option<Option<Option<Option<u8>>>>>>(&options).is_ok());
+        match ::from_bytes_with_config::<Option<Option<Option<Option<Option<u8>>>>>>(&options,
+                                                                                    hardened) {
+            Err(Error::DepthLimitExceeded) => {}
+            other => panic!("expected DepthLimitExceeded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn max_collection_len_test() {
+        use std::collections::BTreeMap;
+
+        let config = DeserializerConfig::new().max_collection_len(10000);
+
+        let rejects = |bytes: &[u8]| match ::from_bytes_with_config::<Vec<u8>>(bytes, config) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        };
+
+        // an array16 header announcing 20000 elements is refused before any element is read
+        rejects(&[0xdc, 0x4e, 0x20]);
+        rejects(&[0xdd, 0x00, 0x00, 0x4e, 0x20]);
+
+        match ::from_bytes_with_config::<BTreeMap<u8, u8>>(&[0xde, 0x4e, 0x20], config) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+
+        // without the cap, the same header only fails once the input runs out
+        assert!(!matches!(::from_bytes::<Vec<u8>>(&[0xdc, 0x4e, 0x20]), Err(Error::TooBig)));
+
+        let at_limit = ::to_bytes(vec![0u8; 10000]).unwrap();
+        assert_eq!(::from_bytes_with_config::<Vec<u8>>(&at_limit, config).unwrap().len(), 10000);
+    }
+
+    #[test]
+    fn little_endian_lengths_test() {
+        let config = DeserializerConfig::new().little_endian_lengths(true);
+
+        // a str16 of five bytes, with its length the wrong way round
+        let fixture = [0xda, 0x05, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f];
+
+        let value: String = ::from_bytes_with_config(&fixture, config).unwrap();
+        assert_eq!(value, "hello");
+
+        // read as the spec says, that's a 1280 byte string running past the end
+        match ::from_bytes::<String>(&fixture) {
+            Err(Error::EndOfStream) => {}
+            other => panic!("expected EndOfStream, got {:?}", other),
+        }
+
+        // array32 lengths are swapped too, but integer values are not
+        let fixture = [0xdd, 0x02, 0x00, 0x00, 0x00, 0xcd, 0x01, 0x2c, 0x07];
+        let value: Vec<u16> = ::from_bytes_with_config(&fixture, config).unwrap();
+        assert_eq!(value, vec![300, 7]);
+    }
+
+    #[test]
+    fn tolerant_array_structs_test() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Record {
+            id: u32,
+            name: String,
+            #[serde(default)]
+            score: i64,
+        }
+
+        let config = DeserializerConfig::new().tolerant_array_structs(true);
+
+        // an older writer that didn't know about score
+        let old = [0x92, 0x07, 0xa1, 0x61];
+        assert_eq!(::from_bytes_with_config::<Record>(&old, config).unwrap(),
+                   Record {
+                       id: 7,
+                       name: "a".into(),
+                       score: 0,
+                   });
+
+        // a newer writer that added a fourth field
+        let new = [0x94, 0x07, 0xa1, 0x61, 0xfe, 0x92, 0xc3, 0xa1, 0x62];
+        assert_eq!(::from_bytes_with_config::<Record>(&new, config).unwrap(),
+                   Record {
+                       id: 7,
+                       name: "a".into(),
+                       score: -2,
+                   });
+
+        // maps and exact arrays still work, and required fields are still required
+        let exact = [0x93, 0x07, 0xa1, 0x61, 0x05];
+        assert_eq!(::from_bytes_with_config::<Record>(&exact, config).unwrap().score, 5);
+        let map = ::to_bytes(Record {
+                                 id: 1,
+                                 name: "b".into(),
+                                 score: 2,
+                             })
+            .unwrap();
+        assert_eq!(::from_bytes_with_config::<Record>(&map, config).unwrap().id, 1);
+        assert!(::from_bytes_with_config::<Record>(&[0x91, 0x07], config).is_err());
+
+        // without the option, extra elements are left unread and throw off whatever follows
+        let mut pair = vec![0x92];
+        pair.extend_from_slice(&new);
+        pair.extend_from_slice(&new);
+        assert_eq!(::from_bytes_with_config::<Vec<Record>>(&pair, config).unwrap().len(), 2);
+        assert!(::from_bytes::<Vec<Record>>(&pair).is_err());
+    }
+
     #[test]
     fn fixarr_test() {
         let v: Vec<u8> = ::from_bytes(&[0x94, 0x05, 0x08, 0x14, 0xcc, 0xe7]).unwrap();
@@ -521,9 +1246,308 @@ mod test {
                                                               0xa5, 0x74, 0x68, 0x72, 0x65, 0x65,
                                                               0x03, 0xa3, 0x74, 0x77, 0x6f, 0x02])
             .unwrap();
-        assert_eq!(map.remove(&format!("one")), Some(1));
-        assert_eq!(map.remove(&format!("two")), Some(2));
-        assert_eq!(map.remove(&format!("three")), Some(3));
+        assert_eq!(map.remove("one"), Some(1));
+        assert_eq!(map.remove("two"), Some(2));
+        assert_eq!(map.remove("three"), Some(3));
         assert!(map.is_empty());
     }
+
+    #[test]
+    fn borrowed_bin_test() {
+        #[derive(Deserialize)]
+        struct Blob<'a> {
+            data: &'a [u8],
+        }
+
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+
+        let mut fixture: Vec<u8> = vec![0x81, 0xa4, 0x64, 0x61, 0x74, 0x61, 0xc5, 0x03, 0xe8];
+        fixture.extend_from_slice(&payload);
+
+        let blob: Blob = ::from_bytes(&fixture).unwrap();
+        assert_eq!(blob.data, &*payload);
+
+        // the slice points into the input rather than a copy of it
+        assert_eq!(blob.data.as_ptr(), fixture[9..].as_ptr());
+    }
+
+    #[test]
+    fn max_allocations_test() {
+        let strings: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let fixture = ::to_bytes(&strings).unwrap();
+
+        // one for the array, plus one per string
+        let config = DeserializerConfig::new().max_allocations(101);
+        let value: Vec<String> = ::from_bytes_with_config(&fixture, config).unwrap();
+        assert_eq!(value, strings);
+
+        let config = DeserializerConfig::new().max_allocations(50);
+        match ::from_bytes_with_config::<Vec<String>>(&fixture, config) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+    }
+
+    fn assert_overflow<T>(value: i128)
+        where T: DeserializeOwned + Debug
+    {
+        let fixture = if value < 0 {
+            ::to_bytes(value as i64).unwrap()
+        } else {
+            ::to_bytes(value as u64).unwrap()
+        };
+
+        match ::from_bytes::<T>(&fixture) {
+            Err(Error::Overflow) => {}
+            other => panic!("expected Overflow for {}, got {:?}", value, other),
+        }
+    }
+
+    #[test]
+    fn overflow_test() {
+        assert_overflow::<u8>(300);
+        assert_overflow::<u8>(-1);
+        assert_overflow::<u16>(70000);
+        assert_overflow::<u32>(1 << 32);
+        assert_overflow::<u64>(-5);
+        assert_overflow::<i8>(200);
+        assert_overflow::<i8>(-129);
+        assert_overflow::<i16>(40000);
+        assert_overflow::<i32>(-3000000000);
+        assert_overflow::<i64>(u64::MAX as i128);
+    }
+
+    #[test]
+    fn narrowing_in_range_test() {
+        let value: u8 = ::from_bytes(&[0xcd, 0x00, 0xff]).unwrap();
+        assert_eq!(value, 255);
+
+        let value: i16 = ::from_bytes(&[0xcf, 0, 0, 0, 0, 0, 0, 0x7f, 0xff]).unwrap();
+        assert_eq!(value, i16::MAX);
+
+        let value: u32 = ::from_bytes(&[0xd0, 0x05]).unwrap();
+        assert_eq!(value, 5);
+    }
+
+    #[test]
+    fn duplicate_key_test() {
+        type Nested = BTreeMap<String, BTreeMap<String, u8>>;
+
+        // {"a": {"b": 1, "b": 2}}
+        let fixture = [0x81, 0xa1, 0x61, 0x82, 0xa1, 0x62, 0x01, 0xa1, 0x62, 0x02];
+
+        // last one wins by default
+        let value: Nested = ::from_bytes(&fixture).unwrap();
+        assert_eq!(value["a"]["b"], 2);
+
+        let config = DeserializerConfig::new().deny_duplicate_keys(true);
+        match ::from_bytes_with_config::<Nested>(&fixture, config) {
+            Err(Error::DuplicateKey(ref path)) if path == "/a" => {}
+            other => panic!("expected DuplicateKey at /a, got {:?}", other),
+        }
+
+        // {"list": [{}, {"x": 1, "x": 2}]}
+        let fixture = [0x81, 0xa4, 0x6c, 0x69, 0x73, 0x74, 0x92, 0x80, 0x82, 0xa1, 0x78, 0x01,
+                       0xa1, 0x78, 0x02];
+        match ::from_bytes_with_config::<BTreeMap<String, Vec<BTreeMap<String, u8>>>>(&fixture,
+                                                                                     config) {
+            Err(Error::DuplicateKey(ref path)) if path == "/list/1" => {}
+            other => panic!("expected DuplicateKey at /list/1, got {:?}", other),
+        }
+
+        // distinct keys are fine
+        let fixture = [0x81, 0xa1, 0x61, 0x82, 0xa1, 0x62, 0x01, 0xa1, 0x63, 0x02];
+        let value: Nested = ::from_bytes_with_config(&fixture, config).unwrap();
+        assert_eq!(value["a"]["c"], 2);
+
+        // a large map is checked in one pass, and a repeat of its first key is still caught
+        let map_of = |keys: &[u32]| {
+            let mut bytes = vec![0xdf];
+            bytes.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+            for &key in keys {
+                bytes.extend(::to_bytes(key).unwrap());
+                bytes.push(0xc0);
+            }
+            bytes
+        };
+
+        let mut keys: Vec<u32> = (0..40000).collect();
+        let bytes = map_of(&keys);
+        assert_eq!(::from_bytes_with_config::<BTreeMap<u32, ()>>(&bytes, config).unwrap().len(),
+                   40000);
+
+        keys.push(0);
+        let bytes = map_of(&keys);
+        assert!(::from_bytes_with_config::<BTreeMap<u32, ()>>(&bytes, config).is_err());
+    }
+
+    #[test]
+    fn duplicate_integer_key_test() {
+        // {1: "a", 1: "b"}, with the first key a uint8 and the second an int8
+        let fixture = [0x82, 0xcc, 0x01, 0xa1, 0x61, 0xd0, 0x01, 0xa1, 0x62];
+
+        let value: BTreeMap<i64, String> = ::from_bytes(&fixture).unwrap();
+        assert_eq!(value[&1], "b");
+
+        let config = DeserializerConfig::new().deny_duplicate_keys(true);
+        match ::from_bytes_with_config::<BTreeMap<i64, String>>(&fixture, config) {
+            Err(Error::DuplicateKey(ref path)) if path == "/" => {}
+            other => panic!("expected DuplicateKey at /, got {:?}", other),
+        }
+
+        // a fixint and an int16 holding the same negative number collide too
+        let fixture = [0x82, 0xff, 0xc0, 0xd1, 0xff, 0xff, 0xc0];
+        assert!(::from_bytes_with_config::<BTreeMap<i64, ()>>(&fixture, config).is_err());
+
+        // but a number and the string of its digits don't
+        let fixture = [0x82, 0x01, 0xc0, 0xa1, 0x31, 0xc0];
+        assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(&fixture, config).is_ok());
+    }
+
+    #[test]
+    fn float_key_test() {
+        let config = DeserializerConfig::new().deny_duplicate_keys(true);
+
+        // {NaN: nil}
+        let mut nan = vec![0x81, 0xcb];
+        nan.extend_from_slice(&f64::NAN.to_bits().to_be_bytes());
+        nan.push(0xc0);
+        match ::from_bytes_with_config::<::serde::de::IgnoredAny>(&nan, config) {
+            Err(Error::InvalidKey) => {}
+            other => panic!("expected InvalidKey, got {:?}", other),
+        }
+        assert!(::from_bytes::<::serde::de::IgnoredAny>(&nan).is_ok());
+
+        // {0.0: nil, -0.0: nil}
+        let zeros = [0x82, 0xcb, 0, 0, 0, 0, 0, 0, 0, 0, 0xc0, 0xcb, 0x80, 0, 0, 0, 0, 0, 0, 0,
+                     0xc0];
+        match ::from_bytes_with_config::<::serde::de::IgnoredAny>(&zeros, config) {
+            Err(Error::DuplicateKey(ref path)) if path == "/" => {}
+            other => panic!("expected DuplicateKey at /, got {:?}", other),
+        }
+        assert!(::from_bytes::<::serde::de::IgnoredAny>(&zeros).is_ok());
+
+        // {1.5f32: nil, 1.5f64: nil} collide, but {1: nil, 1.0: nil} don't
+        let widths = [0x82, 0xca, 0x3f, 0xc0, 0, 0, 0xc0, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0,
+                      0xc0];
+        assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(&widths, config).is_err());
+
+        let mixed = [0x82, 0x01, 0xc0, 0xcb, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0, 0xc0];
+        assert!(::from_bytes_with_config::<::serde::de::IgnoredAny>(&mixed, config).is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_test() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Person {
+            name: String,
+            age: u8,
+        }
+
+        let mut fixture = vec![0x84];
+        // "unknown1": {"deep": [1, nil, b"\x00"]}
+        fixture.extend_from_slice(b"\xa8unknown1\x81\xa4deep\x93\x01\xc0\xc4\x01\x00");
+        // "name": "x"
+        fixture.extend_from_slice(b"\xa4name\xa1x");
+        // "unknown2": a string that isn't UTF-8, which is skipped without being checked
+        fixture.extend_from_slice(b"\xa8unknown2\xa2\xff\xfe");
+        // "age": 5
+        fixture.extend_from_slice(b"\xa3age\x05");
+
+        let person: Person = ::from_bytes(&fixture).unwrap();
+        assert_eq!(person,
+                   Person {
+                       name: "x".into(),
+                       age: 5,
+                   });
+    }
+
+    #[test]
+    fn buf_read_test() {
+        use std::fmt;
+        use std::io::{BufReader, Read};
+
+        use serde::{Deserialize, Deserializer as SerdeDeserializer};
+        use serde::de::Visitor;
+
+        use Deserializer;
+
+        // measures a string without keeping it
+        struct Len;
+
+        impl<'de> Visitor<'de> for Len {
+            type Value = usize;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a string")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<usize, E> {
+                Ok(value.len())
+            }
+        }
+
+        let long = "a string too long for the buffer";
+        let mut bytes = ::to_bytes(("abc", 70000u32)).unwrap();
+        bytes.extend(::to_bytes("in buffer").unwrap());
+        bytes.extend(::to_bytes(long).unwrap());
+        bytes.push(0xc0);
+
+        let mut reader = BufReader::with_capacity(32, &bytes[..]);
+
+        {
+            let mut de = Deserializer::from_buf_read(&mut reader);
+
+            let value: (String, u32) = Deserialize::deserialize(&mut de).unwrap();
+            assert_eq!(value, ("abc".into(), 70000));
+
+            // a string that's already buffered is handed over from the buffer
+            assert_eq!((&mut de).deserialize_str(Len).unwrap(), 9);
+
+            // one that straddles refills is gathered up first
+            assert_eq!((&mut de).deserialize_str(Len).unwrap(), long.len());
+        }
+
+        // the reader is left just past the last value
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &[0xc0]);
+
+        let mut de = Deserializer::from_buf_read(&[0xa3, 0x61][..]);
+        match String::deserialize(&mut de) {
+            Err(Error::EndOfStream) => {}
+            other => panic!("expected EndOfStream, got {:?}", other),
+        }
+
+        let config = DeserializerConfig::new().max_str_len(8);
+        let mut de = Deserializer::from_buf_read_with_config(&bytes[..], config);
+        assert_eq!(<(String, u32)>::deserialize(&mut de).unwrap(), ("abc".into(), 70000));
+        match String::deserialize(&mut de) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strict_bool_test() {
+        assert!(::from_bytes::<bool>(&[0xc3]).unwrap());
+        assert!(!::from_bytes::<bool>(&[0xc2]).unwrap());
+
+        for fixture in &[&[0x01][..], &[0x00], &[0xc0], &[0xa1, 0x31]] {
+            match ::from_bytes::<bool>(fixture) {
+                Err(Error::BadType) => {}
+                other => panic!("expected BadType for {:?}, got {:?}", fixture, other),
+            }
+        }
+    }
+
+    #[test]
+    fn str32_test() {
+        let s = "a".repeat(0x10203);
+        let mut fixture: Vec<u8> = vec![0xdb, 0x00, 0x01, 0x02, 0x03];
+        fixture.extend_from_slice(s.as_bytes());
+
+        let value: String = ::from_bytes(&fixture).unwrap();
+        assert_eq!(value, s);
+    }
 }
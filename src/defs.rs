@@ -31,6 +31,11 @@ pub const MAX_BIN8: usize = 0xff;
 pub const MAX_BIN16: usize = 0xffff;
 pub const MAX_BIN32: usize = 0xffff_ffff;
 
+// extension payload limits
+pub const MAX_EXT8: usize = 0xff;
+pub const MAX_EXT16: usize = 0xffff;
+pub const MAX_EXT32: usize = 0xffff_ffff;
+
 pub struct InclusiveRange<T> {
     pub start: T,
     pub end: T,
@@ -109,7 +114,17 @@ pub const FIXSTR_MASK: u8 = 0b1010_0000;
 pub const U64_BYTES: usize = 8;
 pub const U32_BYTES: usize = 4;
 pub const U16_BYTES: usize = 2;
+pub const TIMESTAMP96_BYTES: usize = U32_BYTES + U64_BYTES;
 
 pub fn read_signed(unsigned: u8) -> i8 {
     LittleEndian::read_i16(&[unsigned, 0]) as i8
 }
+
+/// Whether the given type byte starts a string, byte array, extension, array or map: the values
+/// that may need a heap allocation to decode, whether or not a particular decode makes one.
+pub fn is_heap_value(ty: u8) -> bool {
+    FIXMAP.contains(ty) || FIXARRAY.contains(ty) || FIXSTR.contains(ty) ||
+    matches!(ty,
+             BIN8 | BIN16 | BIN32 | EXT8 | EXT16 | EXT32 | FIXEXT1 | FIXEXT2 | FIXEXT4 |
+             FIXEXT8 | FIXEXT16 | STR8 | STR16 | STR32 | ARRAY16 | ARRAY32 | MAP16 | MAP32)
+}
@@ -16,7 +16,11 @@ use std::str::Utf8Error;
 use std::fmt;
 
 /// Reasons that parsing or encoding might fail in corepack.
+///
+/// New variants may be added without a major version bump, so matches on this type need a
+/// wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Container or sequence was too big to serialize.
     TooBig,
@@ -30,28 +34,82 @@ pub enum Error {
     /// Invalid length encountered.
     BadLength,
 
+    /// Bytes were left over after the value was decoded.
+    TrailingData,
+
+    /// Containers were nested deeper than allowed.
+    DepthLimitExceeded,
+
+    /// A map key was not allowed, such as a NaN float.
+    InvalidKey,
+
+    /// An extension value was malformed or not allowed.
+    Ext,
+
+    /// A decoded number does not fit in the requested type.
+    Overflow,
+
+    /// A float was NaN or infinite where only finite values are allowed.
+    NonFinite,
+
+    /// The output sink is full and must be drained before trying again.
+    WouldBlock,
+
+    /// A map contained the same key more than once. Contains the path to the map.
+    DuplicateKey(String),
+
+    /// A document checked for canonical form wasn't canonical. Contains the offset of the
+    /// offending value and what was wrong with it.
+    NonCanonical(usize, &'static str),
+
+    /// A buffer checked for well-formedness wasn't well-formed. Contains the offset of the
+    /// offending value and what was wrong with it.
+    Malformed(usize, &'static str),
+
     /// Error decoding UTF8 string.
     Utf8Error(Utf8Error),
 
+    /// Error from an underlying reader or writer.
+    #[cfg(feature = "std")]
+    Io(::std::io::Error),
+
     /// Some other error that does not fit into the above.
     Other(String),
 }
 
 impl Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.write_str(self.description())
+        match *self {
+            Error::DuplicateKey(ref path) => write!(fmt, "{} at {}", self.description(), path),
+            Error::NonCanonical(offset, reason) | Error::Malformed(offset, reason) => {
+                write!(fmt, "{} at byte {}: {}", self.description(), offset, reason)
+            }
+            _ => fmt.write_str(self.description()),
+        }
     }
 }
 
 impl Error {
     fn description(&self) -> &str {
-        match self {
-            &Error::TooBig => "Overflowing value",
-            &Error::EndOfStream => "End of stream",
-            &Error::BadType => "Invalid type",
-            &Error::BadLength => "Invalid length",
-            &Error::Utf8Error(_) => "UTF8 Error",
-            &Error::Other(ref message) => &message,
+        match *self {
+            Error::TooBig => "Overflowing value",
+            Error::EndOfStream => "End of stream",
+            Error::BadType => "Invalid type",
+            Error::BadLength => "Invalid length",
+            Error::TrailingData => "Trailing data",
+            Error::DepthLimitExceeded => "Depth limit exceeded",
+            Error::InvalidKey => "Invalid map key",
+            Error::Ext => "Invalid extension",
+            Error::Overflow => "Numeric overflow",
+            Error::NonFinite => "Non-finite float",
+            Error::WouldBlock => "Would block",
+            Error::DuplicateKey(_) => "Duplicate map key",
+            Error::NonCanonical(..) => "Non-canonical encoding",
+            Error::Malformed(..) => "Malformed value",
+            Error::Utf8Error(_) => "UTF8 Error",
+            #[cfg(feature = "std")]
+            Error::Io(_) => "IO Error",
+            Error::Other(ref message) => message,
         }
     }
 }
@@ -62,15 +120,23 @@ impl From<Utf8Error> for Error {
     }
 }
 
+#[cfg(feature = "std")]
+impl From<::std::io::Error> for Error {
+    fn from(cause: ::std::io::Error) -> Error {
+        Error::Io(cause)
+    }
+}
+
 #[cfg(feature = "std")]
 impl ::std::error::Error for Error {
     fn description(&self) -> &str {
         Error::description(self)
     }
 
-    fn cause(&self) -> Option<&::std::error::Error> {
-        match self {
-            &Error::Utf8Error(ref cause) => Some(cause),
+    fn source(&self) -> Option<&(dyn (::std::error::Error) + 'static)> {
+        match *self {
+            Error::Utf8Error(ref cause) => Some(cause),
+            Error::Io(ref cause) => Some(cause),
             _ => None,
         }
     }
@@ -7,7 +7,8 @@
 use alloc::borrow::ToOwned;
 
 use serde::de::{MapAccess, DeserializeSeed, IntoDeserializer};
-use serde::de::value::{StrDeserializer, I8Deserializer, SeqDeserializer};
+use serde::de::value::{StrDeserializer, I8Deserializer, I64Deserializer, U32Deserializer,
+                       SeqDeserializer};
 
 use error::Error;
 
@@ -17,12 +18,18 @@ pub struct ExtDeserializer<'a> {
     data: &'a [u8],
 }
 
+pub struct TimestampDeserializer {
+    state: u8,
+    seconds: i64,
+    nanoseconds: u32,
+}
+
 impl<'a> ExtDeserializer<'a> {
     pub fn new(ty: i8, data: &'a [u8]) -> ExtDeserializer<'a> {
         ExtDeserializer {
             state: 0,
-            ty: ty,
-            data: data,
+            ty,
+            data,
         }
     }
 }
@@ -35,10 +42,10 @@ impl<'de, 'a> MapAccess<'de> for ExtDeserializer<'a> {
     {
         if self.state == 0 {
             let de: StrDeserializer<Self::Error> = "type".into_deserializer();
-            Ok(Some(try!(seed.deserialize(de))))
+            Ok(Some(seed.deserialize(de)?))
         } else if self.state == 1 {
             let de: StrDeserializer<Self::Error> = "data".into_deserializer();
-            Ok(Some(try!(seed.deserialize(de))))
+            Ok(Some(seed.deserialize(de)?))
         } else {
             Ok(None)
         }
@@ -50,11 +57,59 @@ impl<'de, 'a> MapAccess<'de> for ExtDeserializer<'a> {
         if self.state == 0 {
             self.state += 1;
             let de: I8Deserializer<Self::Error> = self.ty.into_deserializer();
-            Ok(try!(seed.deserialize(de)))
+            Ok(seed.deserialize(de)?)
         } else if self.state == 1 {
             self.state += 1;
             let de: SeqDeserializer<_, Self::Error> = self.data.to_owned().into_deserializer();
-            Ok(try!(seed.deserialize(de)))
+            Ok(seed.deserialize(de)?)
+        } else {
+            Err(Error::EndOfStream)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(2 - self.state as usize)
+    }
+}
+
+impl TimestampDeserializer {
+    pub fn new(seconds: i64, nanoseconds: u32) -> TimestampDeserializer {
+        TimestampDeserializer {
+            state: 0,
+            seconds,
+            nanoseconds,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for TimestampDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+        where T: DeserializeSeed<'de>
+    {
+        if self.state == 0 {
+            let de: StrDeserializer<Self::Error> = "seconds".into_deserializer();
+            Ok(Some(seed.deserialize(de)?))
+        } else if self.state == 1 {
+            let de: StrDeserializer<Self::Error> = "nanoseconds".into_deserializer();
+            Ok(Some(seed.deserialize(de)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Self::Error>
+        where T: DeserializeSeed<'de>
+    {
+        if self.state == 0 {
+            self.state += 1;
+            let de: I64Deserializer<Self::Error> = self.seconds.into_deserializer();
+            Ok(seed.deserialize(de)?)
+        } else if self.state == 1 {
+            self.state += 1;
+            let de: U32Deserializer<Self::Error> = self.nanoseconds.into_deserializer();
+            Ok(seed.deserialize(de)?)
         } else {
             Err(Error::EndOfStream)
         }
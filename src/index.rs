@@ -0,0 +1,234 @@
+//! Indexed maps, a non-standard envelope for looking up single keys in a large encoded map.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+//
+// An indexed map is written as a two-element array. The first element is a bin value holding the
+// index, and the second is the map, exactly as it would be written on its own. The index has one
+// record of RECORD_LEN bytes per entry: the 64-bit FNV-1a hash of the entry's encoded key,
+// followed by the 32-bit offset of that key from the start of the map, both big-endian. Records
+// are sorted by hash, then by offset.
+//
+// The envelope is still MessagePack, so other implementations can read the map as the second
+// element of the array. A key is found by hashing its encoding, so lookups only match keys
+// encoded the way corepack writes them.
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use serde::{Serialize, Deserialize};
+
+use defs::*;
+use error::Error;
+use scan;
+use ser::Serializer;
+use write::VecWrite;
+
+/// The length of each record in the index.
+pub const RECORD_LEN: usize = U64_BYTES + U32_BYTES;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Wrap an encoded map in an envelope with an index of its keys.
+pub(crate) fn build(map: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut records = vec![];
+
+    for entry in scan::Entries::new(map)? {
+        let (key, _) = entry?;
+
+        if key.start > u32::MAX as usize {
+            return Err(Error::TooBig);
+        }
+
+        records.push((hash(&map[key.clone()]), key.start as u32));
+    }
+
+    records.sort();
+
+    let mut index = vec![0; records.len() * RECORD_LEN];
+    for (record, &(hash, offset)) in index.chunks_mut(RECORD_LEN).zip(&records) {
+        BigEndian::write_u64(record, hash);
+        BigEndian::write_u32(&mut record[U64_BYTES..], offset);
+    }
+
+    let mut bytes = vec![2 | FIXARRAY_MASK];
+
+    {
+        let mut ser = Serializer::from_write(VecWrite::new(&mut bytes));
+
+        ::serde::Serializer::serialize_bytes(&mut ser, &index)?;
+    }
+
+    bytes.extend_from_slice(map);
+
+    Ok(bytes)
+}
+
+/// A map written by `to_vec_indexed`, whose values can be looked up by key without decoding the
+/// rest of the map.
+pub struct IndexedMap<'a> {
+    index: &'a [u8],
+    map: &'a [u8],
+}
+
+impl<'a> IndexedMap<'a> {
+    /// Read the envelope written by `to_vec_indexed`. Only the headers are checked here, so this
+    /// takes the same time however large the map is; each lookup reads just the index records and
+    /// entry it needs, and fails if those turn out to be malformed.
+    pub fn new(bytes: &'a [u8]) -> Result<IndexedMap<'a>, Error> {
+        match bytes.first() {
+            Some(&ty) if ty == 2 | FIXARRAY_MASK => {}
+            Some(_) => return Err(Error::BadType),
+            None => return Err(Error::EndOfStream),
+        }
+
+        match bytes.get(1) {
+            Some(&BIN8) | Some(&BIN16) | Some(&BIN32) => {}
+            Some(_) => return Err(Error::BadType),
+            None => return Err(Error::EndOfStream),
+        }
+
+        let (header_len, index_len, _) = scan::header(bytes, 1)?;
+        let start = 1 + header_len;
+        let index = bytes.get(start..start + index_len).ok_or(Error::EndOfStream)?;
+        let map = &bytes[start + index_len..];
+
+        if index_len % RECORD_LEN != 0 {
+            return Err(Error::BadLength);
+        }
+
+        if scan::map_header(map)?.0 != index_len / RECORD_LEN {
+            return Err(Error::BadLength);
+        }
+
+        Ok(IndexedMap { index, map })
+    }
+
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.index.len() / RECORD_LEN
+    }
+
+    /// Whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The bytes of the map itself, as it would be written without the index.
+    pub fn map(&self) -> &'a [u8] {
+        self.map
+    }
+
+    /// Decode the value stored under a key, or return `None` if the map has no such key.
+    ///
+    /// The index is binary searched for the key's hash, and only the entries with that hash are
+    /// read.
+    pub fn get<K, V>(&self, key: &K) -> Result<Option<V>, Error>
+        where K: ?Sized + Serialize,
+              V: Deserialize<'a>
+    {
+        let key = ::to_bytes(key)?;
+        let hash = hash(&key);
+
+        // find the first record with this hash
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            if self.record(middle).0 < hash {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        for position in low..self.len() {
+            let (record_hash, offset) = self.record(position);
+            if record_hash != hash {
+                break;
+            }
+
+            let entry = self.map.get(offset..).ok_or(Error::BadLength)?;
+            let key_len = scan::value_len(entry)?;
+
+            if entry[..key_len] == key[..] {
+                let value = &entry[key_len..];
+                let value_len = scan::value_len(value)?;
+
+                return ::from_bytes(&value[..value_len]).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn record(&self, position: usize) -> (u64, usize) {
+        let record = &self.index[position * RECORD_LEN..];
+
+        (BigEndian::read_u64(record), BigEndian::read_u32(&record[U64_BYTES..]) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::de::IgnoredAny;
+
+    use super::IndexedMap;
+
+    #[test]
+    fn lookup_test() {
+        let map: BTreeMap<String, u32> = (0..100).map(|i| (format!("key{}", i), i)).collect();
+
+        let mut bytes = ::to_vec_indexed(&map).unwrap();
+        assert_eq!(&bytes[..4], &[0x92, 0xc5, 0x04, 0xb0]);
+
+        // the map is plain MessagePack after the index
+        let (_, decoded): (IgnoredAny, BTreeMap<String, u32>) = ::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, map);
+
+        // break every value but one, which is then the only one that can be decoded
+        let target = ::to_bytes("key42").unwrap();
+        // past the array header, the index and the map header
+        let mut position = 1 + 3 + 100 * super::RECORD_LEN + 3;
+        for _ in 0..100 {
+            let key_len = ::value_len(&bytes[position..]).unwrap();
+            let value_len = ::value_len(&bytes[position + key_len..]).unwrap();
+            if bytes[position..position + key_len] != target[..] {
+                bytes[position + key_len] = 0xc2;
+            }
+            position += key_len + value_len;
+        }
+        assert_eq!(position, bytes.len());
+
+        let indexed = IndexedMap::new(&bytes).unwrap();
+        assert_eq!(indexed.len(), 100);
+        assert_eq!(indexed.get::<_, u32>("key42").unwrap(), Some(42));
+        assert_eq!(indexed.get::<_, u32>("key100").unwrap(), None);
+        assert!(indexed.get::<_, u32>("key7").is_err());
+    }
+
+    #[test]
+    fn envelope_test() {
+        let empty: BTreeMap<u8, u8> = BTreeMap::new();
+        let bytes = ::to_vec_indexed(&empty).unwrap();
+        assert_eq!(bytes, &[0x92, 0xc4, 0x00, 0x80]);
+
+        let indexed = IndexedMap::new(&bytes).unwrap();
+        assert!(indexed.is_empty());
+        assert_eq!(indexed.get::<_, u8>(&1).unwrap(), None);
+
+        // only maps can be indexed, and only envelopes read back
+        assert!(::to_vec_indexed(&[1, 2]).is_err());
+        assert!(IndexedMap::new(&::to_bytes(&empty).unwrap()).is_err());
+        assert!(IndexedMap::new(&[0x92, 0xc4, 0x01, 0x00, 0x80]).is_err());
+    }
+}
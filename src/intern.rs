@@ -0,0 +1,155 @@
+//! String interning, a non-standard wire mode for documents with many repeated strings.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+//
+// When interning is enabled on both sides, every string of at least MIN_INTERNED_LEN bytes is
+// numbered in the order it first appears. The first occurrence is written as a plain string, and
+// each later occurrence is written as an extension of type STRING_REF_TYPE holding the number as
+// a big-endian 1, 2 or 4-byte integer. Other MessagePack implementations will see those
+// extensions as opaque values, so only use this mode when corepack reads the output back.
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::{BTreeMap, String};
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use defs::*;
+use error::Error;
+
+/// The application extension type used to refer back to an interned string.
+pub const STRING_REF_TYPE: i8 = 0x7f;
+
+/// Strings shorter than this are always written out, since a reference would be no smaller.
+pub const MIN_INTERNED_LEN: usize = 4;
+
+/// The strings a serializer has written so far, with the number each was given.
+#[derive(Default)]
+pub(crate) struct StringTable {
+    indices: BTreeMap<String, u32>,
+}
+
+impl StringTable {
+    /// Look up a string that has been written before. A string long enough to intern that hasn't
+    /// been seen yet is given the next number, and None is returned so it gets written out.
+    pub(crate) fn intern(&mut self, value: &str) -> Option<u32> {
+        if value.len() < MIN_INTERNED_LEN {
+            return None;
+        }
+
+        if let Some(&index) = self.indices.get(value) {
+            return Some(index);
+        }
+
+        // once every number is taken, new strings are just written out
+        if self.indices.len() > u32::MAX as usize {
+            return None;
+        }
+
+        let next = self.indices.len() as u32;
+        self.indices.insert(value.into(), next);
+
+        None
+    }
+}
+
+/// Encode a reference to an interned string.
+pub(crate) fn encode_ref(index: u32, buf: &mut [u8; U32_BYTES + 2]) -> &[u8] {
+    buf[1] = STRING_REF_TYPE as u8;
+
+    if index <= u8::MAX as u32 {
+        buf[0] = FIXEXT1;
+        buf[2] = index as u8;
+        &buf[..3]
+    } else if index <= u16::MAX as u32 {
+        buf[0] = FIXEXT2;
+        BigEndian::write_u16(&mut buf[2..], index as u16);
+        &buf[..U16_BYTES + 2]
+    } else {
+        buf[0] = FIXEXT4;
+        BigEndian::write_u32(&mut buf[2..], index);
+        &buf[..]
+    }
+}
+
+/// Decode the number held by a string reference extension.
+pub(crate) fn decode_ref(data: &[u8]) -> Result<usize, Error> {
+    match data.len() {
+        1 => Ok(data[0] as usize),
+        U16_BYTES => Ok(BigEndian::read_u16(data) as usize),
+        U32_BYTES => Ok(BigEndian::read_u32(data) as usize),
+        _ => Err(Error::Ext),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use config::{SerializerConfig, DeserializerConfig};
+
+    #[test]
+    fn repeated_strings_test() {
+        let labels: Vec<String> = (0..100)
+            .map(|i| ["fruit", "vegetable", "grain"][i % 3].to_string())
+            .collect();
+
+        let plain = ::to_bytes(&labels).unwrap();
+        let interned =
+            ::to_bytes_with_config(&labels, SerializerConfig::new().intern_strings(true)).unwrap();
+
+        assert!(interned.len() < plain.len() / 2);
+
+        // only the first occurrence of each label is written out
+        assert_eq!(&interned[3..9], b"\xa5fruit");
+        assert_eq!(&interned[25..28], &[0xd4, 0x7f, 0x00]);
+
+        let config = DeserializerConfig::new().intern_strings(true);
+        let decoded: Vec<String> = ::from_bytes_with_config(&interned, config).unwrap();
+        assert_eq!(decoded, labels);
+    }
+
+    #[test]
+    fn nested_and_short_strings_test() {
+        let value = (vec!["abcd", "ab"], vec![("abcd", "ab")]);
+
+        let bytes =
+            ::to_bytes_with_config(&value, SerializerConfig::new().intern_strings(true)).unwrap();
+
+        // short strings are never referenced, and the table is shared across nesting
+        assert_eq!(bytes,
+                   &[0x92, 0x92, 0xa4, 0x61, 0x62, 0x63, 0x64, 0xa2, 0x61, 0x62, 0x91, 0x92,
+                     0xd4, 0x7f, 0x00, 0xa2, 0x61, 0x62]);
+
+        let config = DeserializerConfig::new().intern_strings(true);
+        let decoded: (Vec<String>, Vec<(String, String)>) =
+            ::from_bytes_with_config(&bytes, config).unwrap();
+        assert_eq!(decoded,
+                   (vec!["abcd".into(), "ab".into()], vec![("abcd".into(), "ab".into())]));
+
+        // a reference past the end of the table is rejected
+        assert!(::from_bytes_with_config::<String>(&[0xd4, 0x7f, 0x00], config).is_err());
+    }
+
+    #[test]
+    fn total_alloc_test() {
+        use error::Error;
+
+        let labels = vec!["a label of thirty-two bytes long"; 10];
+        let bytes =
+            ::to_bytes_with_config(&labels, SerializerConfig::new().intern_strings(true)).unwrap();
+
+        // the string, the table's copy of it, and nine one-byte references that each stand for it
+        let limit = 11 * 32 + 9;
+        let config = DeserializerConfig::new().intern_strings(true).max_total_alloc(limit);
+        assert_eq!(::from_bytes_with_config::<Vec<String>>(&bytes, config).unwrap(), labels);
+
+        // a few bytes of reference can't stand in for more than the limit allows
+        let config = config.max_total_alloc(limit - 1);
+        match ::from_bytes_with_config::<Vec<String>>(&bytes, config) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+    }
+}
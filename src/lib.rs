@@ -24,18 +24,35 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::Vec;
 
+use byteorder::{ByteOrder, BigEndian};
+
+use defs::{BIN8, BIN16, BIN32, U16_BYTES, U32_BYTES};
+
 pub use ser::Serializer;
 pub use de::Deserializer;
+pub use config::{SerializerConfig, DeserializerConfig};
 
+pub mod config;
 pub mod error;
+pub mod index;
+pub mod intern;
+pub mod raw;
 pub mod read;
+pub mod ring_buffer;
+pub mod timestamp;
+pub mod with;
+pub mod write;
 
 mod defs;
+mod scan;
 mod seq_serializer;
 mod map_serializer;
+mod none_probe;
 mod variant_deserializer;
 mod ext_deserializer;
 mod seq_deserializer;
+mod single_deserializer;
+mod positional_deserializer;
 
 mod ser;
 mod de;
@@ -46,9 +63,9 @@ pub fn from_iter<I, V>(mut iter: I) -> Result<V, error::Error>
           V: serde::de::DeserializeOwned
 {
     let mut de = Deserializer::new(read::CopyRead::new(|buf: &mut [u8]| {
-        for i in 0..buf.len() {
+        for slot in buf.iter_mut() {
             if let Some(byte) = iter.next() {
-                buf[i] = byte;
+                *slot = byte;
             } else {
                 return Err(error::Error::EndOfStream);
             }
@@ -60,14 +77,67 @@ pub fn from_iter<I, V>(mut iter: I) -> Result<V, error::Error>
     V::deserialize(&mut de)
 }
 
+/// Parse V out of an `std::io::Read` source, such as a file or socket, without reading the whole
+/// input into memory first.
+///
+/// Short reads are retried until each value is complete, and only the bytes of the value are
+/// read, so the source can hold more data after it. Values are read in many small pieces, so
+/// wrap unbuffered sources in a `std::io::BufReader`.
+#[cfg(feature = "std")]
+pub fn from_read<R, V>(reader: R) -> Result<V, error::Error>
+    where R: std::io::Read,
+          V: serde::de::DeserializeOwned
+{
+    from_read_with_config(reader, config::DeserializerConfig::default())
+}
+
+/// Parse V out of an `std::io::Read` source like `from_read`, using the given deserializer
+/// configuration.
+#[cfg(feature = "std")]
+pub fn from_read_with_config<R, V>(reader: R,
+                                   config: config::DeserializerConfig)
+                                   -> Result<V, error::Error>
+    where R: std::io::Read,
+          V: serde::de::DeserializeOwned
+{
+    V::deserialize(&mut Deserializer::from_read_with_config(reader, config))
+}
+
 /// Parse V out of a slice of bytes.
 pub fn from_bytes<'a, V>(bytes: &'a [u8]) -> Result<V, error::Error>
     where V: serde::Deserialize<'a>
+{
+    from_bytes_with_config(bytes, config::DeserializerConfig::default())
+}
+
+/// Parse V out of a slice of bytes. The same as `from_bytes`, named to match other serde formats.
+pub fn from_slice<'a, V>(bytes: &'a [u8]) -> Result<V, error::Error>
+    where V: serde::Deserialize<'a>
+{
+    from_bytes(bytes)
+}
+
+/// Parse V out of a slice of bytes, using the given deserializer configuration.
+pub fn from_bytes_with_config<'a, V>(bytes: &'a [u8],
+                                     config: config::DeserializerConfig)
+                                     -> Result<V, error::Error>
+    where V: serde::Deserialize<'a>
+{
+    from_bytes_measuring_depth(bytes, config).map(|(value, _)| value)
+}
+
+/// Parse V out of a slice of bytes like `from_bytes_with_config`, also returning the deepest
+/// nesting the input reached, as reported by `Deserializer::peak_depth`.
+///
+/// Tracking this over time shows when incoming documents are growing structurally deeper.
+pub fn from_bytes_measuring_depth<'a, V>(bytes: &'a [u8],
+                                         config: config::DeserializerConfig)
+                                         -> Result<(V, usize), error::Error>
+    where V: serde::Deserialize<'a>
 {
     let mut position: usize = 0;
 
-    let mut de = Deserializer::new(read::BorrowRead::new(|len: usize| if position + len >
-                                                                         bytes.len() {
+    let read = read::BorrowRead::new(|len: usize| if position + len > bytes.len() {
         Err(error::Error::EndOfStream)
     } else {
         let result = &bytes[position..position + len];
@@ -75,14 +145,276 @@ pub fn from_bytes<'a, V>(bytes: &'a [u8]) -> Result<V, error::Error>
         position += len;
 
         Ok(result)
-    }));
+    });
 
-    V::deserialize(&mut de)
+    let mut de = Deserializer::with_config(read, config);
+
+    let value = V::deserialize(&mut de)?;
+
+    Ok((value, de.peak_depth()))
+}
+
+/// Decode a bin value at the start of a slice of bytes, returning its payload and the total
+/// number of bytes the value occupied.
+///
+/// This copies the payload out in one go, without going through a serde visitor.
+pub fn decode_bin(bytes: &[u8]) -> Result<(Vec<u8>, usize), error::Error> {
+    let (len, header) = match bytes.first() {
+        Some(&BIN8) if bytes.len() > 1 => (bytes[1] as usize, 2),
+        Some(&BIN16) if bytes.len() > U16_BYTES => {
+            (BigEndian::read_u16(&bytes[1..]) as usize, U16_BYTES + 1)
+        }
+        Some(&BIN32) if bytes.len() > U32_BYTES => {
+            (BigEndian::read_u32(&bytes[1..]) as usize, U32_BYTES + 1)
+        }
+        None | Some(&BIN8) | Some(&BIN16) | Some(&BIN32) => {
+            return Err(error::Error::EndOfStream);
+        }
+        Some(_) => return Err(error::Error::BadType),
+    };
+
+    let end = header.checked_add(len).ok_or(error::Error::TooBig)?;
+
+    match bytes.get(header..end) {
+        Some(payload) => Ok((payload.to_vec(), end)),
+        None => Err(error::Error::EndOfStream),
+    }
+}
+
+/// Decode a value carried as the payload of a bin value, as written by `to_vec_nested`.
+///
+/// The slice must hold just the one bin value, and its payload just the one nested value. The
+/// nested value is decoded in place, so it can borrow from the slice.
+pub fn from_nested<'a, V>(bytes: &'a [u8]) -> Result<V, error::Error>
+    where V: serde::Deserialize<'a>
+{
+    match bytes.first() {
+        Some(&BIN8) | Some(&BIN16) | Some(&BIN32) => {}
+        Some(_) => return Err(error::Error::BadType),
+        None => return Err(error::Error::EndOfStream),
+    }
+
+    let (header_len, payload_len, _) = scan::header(bytes, 0)?;
+    let end = header_len.checked_add(payload_len).ok_or(error::Error::TooBig)?;
+    let payload = bytes.get(header_len..end).ok_or(error::Error::EndOfStream)?;
+
+    if end != bytes.len() || scan::value_len(payload)? != payload.len() {
+        return Err(error::Error::TrailingData);
+    }
+
+    from_bytes(payload)
+}
+
+/// Decode the value stored under a string key in the map at the start of a slice of bytes.
+///
+/// The entries before the key are skipped over without being decoded, and decoding stops as soon
+/// as the key is found. Returns `None` if the map has no such key.
+pub fn extract_field<'a, V>(bytes: &'a [u8], field: &str) -> Result<Option<V>, error::Error>
+    where V: serde::Deserialize<'a>
+{
+    for entry in scan::Entries::new(bytes)? {
+        let (key, value) = entry?;
+
+        if from_bytes::<&str>(&bytes[key]).ok() == Some(field) {
+            return from_bytes(&bytes[value]).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// The number of bytes taken up by the value at the start of a slice, including everything nested
+/// inside it.
+///
+/// Only the headers are read, so nothing is decoded or allocated. Any bytes after the value are
+/// ignored.
+pub fn value_len(bytes: &[u8]) -> Result<usize, error::Error> {
+    scan::value_len(bytes)
+}
+
+/// Check that a slice of bytes holds exactly one well-formed value, without decoding it.
+///
+/// Every nested value is walked, so this catches bad type bytes, lengths that run past the end,
+/// invalid UTF-8 in strings and trailing bytes, failing with `Error::Malformed` at the first
+/// problem. It's a cheap check to run before a more expensive typed decode.
+pub fn validate(bytes: &[u8]) -> Result<(), error::Error> {
+    scan::validate(bytes, false)
+}
+
+/// Check that a slice of bytes holds any number of well-formed values back to back, like
+/// `validate` does for a single value.
+pub fn validate_stream(bytes: &[u8]) -> Result<(), error::Error> {
+    scan::validate(bytes, true)
+}
+
+/// Find the byte range of each value in a slice holding several values back to back.
+///
+/// Only the headers are read, so this is a cheap way to index a stream of records without
+/// decoding them. Iteration stops after the first error.
+pub fn scan_values<'a>(bytes: &'a [u8])
+                       -> impl Iterator<Item = Result<std::ops::Range<usize>, error::Error>> + 'a {
+    scan::Values::new(bytes)
+}
+
+/// Find the byte range of each value in a slice holding several values back to back, carrying on
+/// past malformed values instead of stopping at the first.
+///
+/// Each malformed value yields an `Error::Malformed` with the offset of the problem, and the scan
+/// picks up at the next position that looks like the start of a value: one where the next 64
+/// bytes hold a well-formed value, followed by the end of the slice or the start of another
+/// well-formed value. Only those bytes are checked at each position, so skipping garbage takes
+/// time linear in its length. This suits log-like data, where losing a damaged record beats
+/// losing the whole file.
+///
+/// Recovery is a heuristic. The bytes left over from a damaged value can themselves look like
+/// small values, so the ranges found just after an error may be bogus, and a bogus container
+/// header can swallow the good values that follow it. Decoding each range as the expected type
+/// weeds out most of the bogus ones.
+pub fn scan_values_recovering<'a>(bytes: &'a [u8])
+    -> impl Iterator<Item = Result<std::ops::Range<usize>, error::Error>> + 'a {
+    scan::Values::recovering(bytes)
+}
+
+/// Check that a slice of bytes holds exactly one value in canonical form, as written by
+/// `SerializerConfig::canonical`.
+///
+/// Fails with `Error::NonCanonical` at the first value that used a wider encoding than it
+/// needed, a non-finite float, map keys that are out of order or repeated, or trailing bytes.
+pub fn verify_canonical(bytes: &[u8]) -> Result<(), error::Error> {
+    scan::verify_canonical(bytes)
 }
 
 /// Serialize V into a byte buffer.
 pub fn to_bytes<V>(value: V) -> Result<Vec<u8>, error::Error>
     where V: serde::Serialize
+{
+    to_bytes_with_config(value, config::SerializerConfig::default())
+}
+
+/// Serialize V into a byte buffer. The same as `to_bytes`, but by reference and named to match
+/// other serde formats.
+pub fn to_vec<V>(value: &V) -> Result<Vec<u8>, error::Error>
+    where V: ?Sized + serde::Serialize
+{
+    to_bytes(value)
+}
+
+/// Serialize V into a byte buffer, using the given serializer configuration.
+pub fn to_bytes_with_config<V>(value: V,
+                               config: config::SerializerConfig)
+                               -> Result<Vec<u8>, error::Error>
+    where V: serde::Serialize
+{
+    let mut bytes = vec![];
+
+    {
+        let mut ser = Serializer::from_write_with_config(write::VecWrite::new(&mut bytes), config);
+
+        value.serialize(&mut ser)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Serialize V into an `std::io::Write` sink, such as a file or socket, and flush it.
+///
+/// Errors from the writer are returned as `Error::Io`. Values are written in many small pieces,
+/// so wrap unbuffered sinks in a `std::io::BufWriter`.
+#[cfg(feature = "std")]
+pub fn to_writer<W, V>(mut writer: W, value: &V) -> Result<(), error::Error>
+    where W: std::io::Write,
+          V: ?Sized + serde::Serialize
+{
+    {
+        let mut ser = Serializer::new(|bytes: &[u8]| {
+            writer.write_all(bytes)?;
+            Ok(())
+        });
+
+        value.serialize(&mut ser)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Serialize V into a byte buffer that starts out with room for `capacity` bytes.
+///
+/// When the encoded size is roughly known, this saves the buffer from growing as it fills.
+pub fn to_vec_with_capacity<V>(value: &V, capacity: usize) -> Result<Vec<u8>, error::Error>
+    where V: ?Sized + serde::Serialize
+{
+    let mut bytes = Vec::with_capacity(capacity);
+
+    {
+        let mut ser = Serializer::from_write(write::VecWrite::new(&mut bytes));
+
+        value.serialize(&mut ser)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Serialize V into a byte buffer, and wrap that in a bin value.
+///
+/// This suits envelope formats that carry an inner document as an opaque payload, for instance
+/// to encrypt or version it separately. Decode the result with `from_nested`.
+pub fn to_vec_nested<V>(value: &V) -> Result<Vec<u8>, error::Error>
+    where V: ?Sized + serde::Serialize
+{
+    let inner = to_bytes(value)?;
+    let mut bytes = Vec::with_capacity(inner.len() + 5);
+
+    {
+        let mut ser = Serializer::from_write(write::VecWrite::new(&mut bytes));
+
+        serde::Serializer::serialize_bytes(&mut ser, &inner)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Serialize a map, preceded by an index of its keys, so that single values can be looked up
+/// with `index::IndexedMap` without decoding the rest of the map.
+///
+/// This is a non-standard envelope, described in the `index` module. Other implementations can
+/// still read the map out of it. Fails with `Error::BadType` if V isn't serialized as a map.
+pub fn to_vec_indexed<V>(value: &V) -> Result<Vec<u8>, error::Error>
+    where V: ?Sized + serde::Serialize
+{
+    index::build(&to_bytes(value)?)
+}
+
+/// Serialize V as lowercase hex digits into a text sink, such as a String or a formatter.
+///
+/// The encoded bytes are turned into digits as they are produced, so no intermediate byte buffer
+/// is allocated.
+pub fn to_hex_into<W, V>(writer: &mut W, value: &V) -> Result<(), error::Error>
+    where W: std::fmt::Write,
+          V: ?Sized + serde::Serialize
+{
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut ser = Serializer::new(|bytes: &[u8]| {
+        for &byte in bytes {
+            writer.write_char(DIGITS[(byte >> 4) as usize] as char)
+                .and_then(|_| writer.write_char(DIGITS[(byte & 0xf) as usize] as char))
+                .map_err(|_| error::Error::Other("Failed to write hex digits".into()))?;
+        }
+
+        Ok(())
+    });
+
+    value.serialize(&mut ser)
+}
+
+/// Serialize each of the values one after another into a single byte buffer.
+///
+/// MessagePack streams need no separator between values, so the result can be read back by
+/// parsing values one at a time until the buffer is exhausted.
+pub fn to_vec_many<V>(values: &[V]) -> Result<Vec<u8>, error::Error>
+    where V: serde::Serialize
 {
     let mut bytes = vec![];
 
@@ -92,7 +424,9 @@ pub fn to_bytes<V>(value: V) -> Result<Vec<u8>, error::Error>
             Ok(())
         });
 
-        try!(value.serialize(&mut ser));
+        for value in values {
+            value.serialize(&mut ser)?;
+        }
     }
 
     Ok(bytes)
@@ -127,7 +461,7 @@ mod test {
 
     #[test]
     fn test_str() {
-        test_through(format!("Hello World!"),
+        test_through("Hello World!".to_string(),
                      &[0xac, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x57, 0x6f, 0x72, 0x6c, 0x64,
                        0x21]);
     }
@@ -142,6 +476,121 @@ mod test {
         test_through(T::A(42), &[0x92, 0x00, 0x2a])
     }
 
+    #[test]
+    fn test_enum_adjacently_tagged() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(tag = "t", content = "c")]
+        enum Shape {
+            Empty,
+            Circle(u8),
+            Rect { w: u8, h: u8 },
+        }
+
+        // the tag is written like a unit variant, as [variant index, nil]
+        test_through(Shape::Empty, &[0x81, 0xa1, 0x74, 0x92, 0x00, 0xc0]);
+        test_through(Shape::Circle(3),
+                     &[0x82, 0xa1, 0x74, 0x92, 0x01, 0xc0, 0xa1, 0x63, 0x03]);
+        test_through(Shape::Rect { w: 1, h: 2 },
+                     &[0x82, // map with two entries
+                       0xa1, // "t": Rect
+                       0x74,
+                       0x92,
+                       0x02,
+                       0xc0,
+                       0xa1, // "c": {"w": 1, "h": 2}
+                       0x63,
+                       0x82,
+                       0xa1,
+                       0x77,
+                       0x01,
+                       0xa1,
+                       0x68,
+                       0x02]);
+
+        // content before the tag is buffered until the tag is known
+        let reordered = [0x82, 0xa1, 0x63, 0x05, 0xa1, 0x74, 0x92, 0x01, 0xc0];
+        assert_eq!(::from_bytes::<Shape>(&reordered).unwrap(), Shape::Circle(5));
+
+        // and the tag may be a bare index, as written under unit_variant_as_uint
+        let config = ::config::SerializerConfig::new().unit_variant_as_uint(true);
+        let bytes = ::to_bytes_with_config(Shape::Circle(3), config).unwrap();
+        assert_eq!(bytes, &[0x82, 0xa1, 0x74, 0x01, 0xa1, 0x63, 0x03]);
+        assert_eq!(::from_bytes::<Shape>(&bytes).unwrap(), Shape::Circle(3));
+    }
+
+    #[test]
+    fn test_enum_untagged_map_values() {
+        use std::collections::HashMap;
+
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum IntOrStr {
+            Int(i64),
+            Str(String),
+            Pair(i64, String),
+        }
+
+        // {"a": 1, "b": "two", "c": [-3, "four"]}
+        let bytes = [0x83, 0xa1, 0x61, 0x01, 0xa1, 0x62, 0xa3, 0x74, 0x77, 0x6f, 0xa1, 0x63,
+                     0x92, 0xfd, 0xa4, 0x66, 0x6f, 0x75, 0x72];
+
+        let map: HashMap<String, IntOrStr> = ::from_bytes(&bytes).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"], IntOrStr::Int(1));
+        assert_eq!(map["b"], IntOrStr::Str("two".into()));
+        assert_eq!(map["c"], IntOrStr::Pair(-3, "four".into()));
+
+        assert_eq!(::to_bytes(IntOrStr::Str("two".into())).unwrap(), &bytes[6..10]);
+        assert!(::from_bytes::<IntOrStr>(&[0xc3]).is_err());
+    }
+
+    #[test]
+    fn test_enum_untagged_retry() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Event {
+            Move { id: u32, x: i32 },
+            Rename { id: u32, name: String },
+        }
+
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(tag = "kind")]
+        enum Tagged {
+            Move { x: i32 },
+        }
+
+        // the first variant matches "id" before failing on "name", so the whole map is replayed
+        let rename = Event::Rename {
+            id: 7,
+            name: "b".into(),
+        };
+        test_through(rename,
+                     &[0x82, 0xa2, 0x69, 0x64, 0x07, 0xa4, 0x6e, 0x61, 0x6d, 0x65, 0xa1, 0x62]);
+        test_through(Event::Move { id: 1, x: -1 },
+                     &[0x82, 0xa2, 0x69, 0x64, 0x01, 0xa1, 0x78, 0xff]);
+
+        // the tag can come after the fields it selects
+        // {"x": 5, "kind": "Move"}
+        let bytes = [0x82, 0xa1, 0x78, 0x05, 0xa4, 0x6b, 0x69, 0x6e, 0x64, 0xa4, 0x4d, 0x6f, 0x76,
+                     0x65];
+        assert_eq!(::from_bytes::<Tagged>(&bytes).unwrap(), Tagged::Move { x: 5 });
+    }
+
+    #[test]
+    fn test_enum_nested() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        enum Outer {
+            Wrap(T),
+            Other,
+        }
+
+        // each level is its own [variant index, value] pair
+        test_through(Outer::Wrap(T::B), &[0x92, 0x00, 0x92, 0x01, 0xc0]);
+        test_through(Outer::Wrap(T::A(7)), &[0x92, 0x00, 0x92, 0x00, 0x07]);
+        test_through(vec![Outer::Other, Outer::Wrap(T::C(1, 2))],
+                     &[0x92, 0x92, 0x01, 0xc0, 0x92, 0x00, 0x92, 0x02, 0x92, 0x01, 0x02]);
+    }
+
     #[test]
     fn test_enum_tuple() {
         test_through(T::C(-3, 22), &[0x92, 0x02, 0x92, 0xfd, 0x16])
@@ -158,7 +607,7 @@ mod test {
                        0x82, // map with two entries
                        0xa1, // entry one, fixstr length one: 'a'
                        0x61,
-                       0xd1, // i16: 9001
+                       0xcd, // u16: 9001
                        0x23,
                        0x29,
                        0xa1, // entry two, fixstr length one: 'b'
@@ -178,6 +627,110 @@ mod test {
                        0x21])
     }
 
+    #[test]
+    fn test_unit_variant_as_uint() {
+        let config = ::SerializerConfig::new().unit_variant_as_uint(true);
+
+        let through = |item: T, expected: &[u8]| {
+            let actual = ::to_bytes_with_config(&item, config).expect("Failed to serialize");
+            assert_eq!(expected, &*actual);
+            assert_eq!(item, ::from_bytes::<T>(&actual).expect("Failed to deserialize"));
+        };
+
+        through(T::B, &[0x01]);
+        through(T::A(42), &[0x92, 0x00, 0x2a]);
+        through(T::C(-3, 22), &[0x92, 0x02, 0x92, 0xfd, 0x16]);
+        through(T::D {
+                    a: 1,
+                    b: "x".into(),
+                },
+                &[0x92, 0x03, 0x82, 0xa1, 0x61, 0x01, 0xa1, 0x62, 0xa1, 0x78]);
+
+        // only unit variants may be bare, and the index must be in range
+        assert!(::from_bytes::<T>(&[0x00]).is_err());
+        assert!(::from_bytes::<T>(&[0x04]).is_err());
+        assert!(::from_bytes::<T>(&[0x92, 0x09, 0xc0]).is_err());
+    }
+
+    #[test]
+    fn test_enum_map_keys() {
+        use std::collections::HashMap;
+
+        #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+        enum Color {
+            Red,
+            Green,
+        }
+
+        let mut map = HashMap::new();
+        map.insert(Color::Green, -1);
+
+        // by default the keys are written like any other unit variant
+        let bytes = ::to_bytes(&map).unwrap();
+        assert_eq!(bytes, &[0x81, 0x92, 0x01, 0xc0, 0xff]);
+        assert_eq!(::from_bytes::<HashMap<Color, i32>>(&bytes).unwrap(), map);
+
+        map.insert(Color::Red, 7);
+
+        let config = ::SerializerConfig::new().unit_variant_as_str(true);
+        let bytes = ::to_bytes_with_config(&map, config).unwrap();
+        let decoded: HashMap<String, i32> = ::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get("Red"), Some(&7));
+        assert_eq!(decoded.get("Green"), Some(&-1));
+        assert_eq!(::from_bytes::<HashMap<Color, i32>>(&bytes).unwrap(), map);
+
+        // names must match a variant, and only unit variants may be written as one
+        assert!(::from_bytes::<Color>(b"\xa4Blue").is_err());
+        assert!(::from_bytes::<T>(b"\xa1A").is_err());
+        assert_eq!(::from_bytes::<T>(b"\xa1B").unwrap(), T::B);
+    }
+
+    #[test]
+    fn test_struct_as_array() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Reading {
+            sensor: u8,
+            celsius: i16,
+            label: String,
+            valid: bool,
+        }
+
+        let config = ::SerializerConfig::new().struct_as_array(true);
+
+        let item = Reading {
+            sensor: 3,
+            celsius: -40,
+            label: "x".into(),
+            valid: true,
+        };
+
+        let actual = ::to_bytes_with_config(&item, config).expect("Failed to serialize");
+        assert_eq!(&[0x94, 0x03, 0xd0, 0xd8, 0xa1, 0x78, 0xc3][..], &*actual);
+        assert_eq!(item, ::from_bytes::<Reading>(&actual).expect("Failed to deserialize"));
+
+        // struct variants go positional as well
+        let actual = ::to_bytes_with_config(&T::D {
+                                                 a: 1,
+                                                 b: "x".into(),
+                                             },
+                                             config)
+            .expect("Failed to serialize");
+        assert_eq!(&[0x92, 0x03, 0x92, 0x01, 0xa1, 0x78][..], &*actual);
+    }
+
+    #[test]
+    fn test_newtype_struct() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Meters(f64);
+
+        test_through(Meters(3.5), &[0xcb, 0x40, 0x0c, 0, 0, 0, 0, 0, 0]);
+
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Name(String);
+
+        test_through(vec![Name("a".into())], &[0x91, 0xa1, 0x61]);
+    }
+
     #[test]
     fn test_option() {
         test_through(Some(7), &[0x92, 0xc3, 0x07])
@@ -198,6 +751,27 @@ mod test {
         test_through('b', &[0xa1, 0x62])
     }
 
+    #[test]
+    fn test_char_multibyte() {
+        test_through('€', &[0xa3, 0xe2, 0x82, 0xac])
+    }
+
+    #[test]
+    fn test_char_as_uint() {
+        let config = ::SerializerConfig::new().char_as_uint(true);
+
+        let bytes = ::to_bytes_with_config('A', config).unwrap();
+        assert_eq!(bytes, &[0x41]);
+        assert_eq!(::from_bytes::<char>(&bytes).unwrap(), 'A');
+
+        let bytes = ::to_bytes_with_config('€', config).unwrap();
+        assert_eq!(bytes, &[0xcd, 0x20, 0xac]);
+        assert_eq!(::from_bytes::<char>(&bytes).unwrap(), '€');
+
+        // surrogates aren't valid chars
+        assert!(::from_bytes::<char>(&[0xcd, 0xd8, 0x00]).is_err());
+    }
+
     #[test]
     fn test_false() {
         test_through(false, &[0xc2])
@@ -218,4 +792,603 @@ mod test {
     fn test_float32() {
         test_through(3.2f32, &[0xca, 0x40, 0x4c, 0xcc, 0xcd])
     }
+
+    #[test]
+    fn test_float32_field() {
+        use std::fmt;
+
+        use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+        /// Only accepts values the deserializer hands over as an f32.
+        #[derive(PartialEq, Debug)]
+        struct Narrow(f32);
+
+        impl<'de> Deserialize<'de> for Narrow {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Narrow, D::Error> {
+                struct NarrowVisitor;
+
+                impl<'de> Visitor<'de> for NarrowVisitor {
+                    type Value = Narrow;
+
+                    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                        fmt.write_str("an f32")
+                    }
+
+                    fn visit_f32<E: de::Error>(self, value: f32) -> Result<Narrow, E> {
+                        Ok(Narrow(value))
+                    }
+                }
+
+                deserializer.deserialize_f32(NarrowVisitor)
+            }
+        }
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Reading {
+            value: f32,
+        }
+
+        let bytes = ::to_bytes(Reading { value: 1.5 }).unwrap();
+        assert_eq!(&bytes[7..], &[0xca, 0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(::from_bytes::<Reading>(&bytes).unwrap(), Reading { value: 1.5 });
+
+        assert_eq!(::from_bytes::<Narrow>(&bytes[7..]).unwrap(), Narrow(1.5));
+        assert!(::from_bytes::<Narrow>(&::to_bytes(1.5f64).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_flatten() {
+        #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+        struct Inner {
+            b: u8,
+            c: u8,
+        }
+
+        #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+        struct Outer {
+            a: u8,
+            #[serde(flatten)]
+            inner: Inner,
+        }
+
+        test_through(Outer {
+                         a: 1,
+                         inner: Inner { b: 2, c: 3 },
+                     },
+                     &[0x83, // a single map with three entries
+                       0xa1, // 'a': 1
+                       0x61,
+                       0x01,
+                       0xa1, // 'b': 2
+                       0x62,
+                       0x02,
+                       0xa1, // 'c': 3
+                       0x63,
+                       0x03])
+    }
+
+    #[test]
+    fn test_to_vec_many() {
+        let a = T::A(42);
+        let b = T::D {
+            a: -7,
+            b: "x".into(),
+        };
+
+        let mut expected = ::to_bytes(&a).unwrap();
+        expected.extend(::to_bytes(&b).unwrap());
+
+        assert_eq!(::to_vec_many(&[a, b]).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use error::Error;
+
+        assert_eq!(Error::EndOfStream.to_string(), "End of stream");
+        assert_eq!(Error::TrailingData.to_string(), "Trailing data");
+        assert_eq!(Error::DuplicateKey("/a".into()).to_string(), "Duplicate map key at /a");
+        assert_eq!(Error::DepthLimitExceeded.to_string(), "Depth limit exceeded");
+        assert_eq!(Error::Overflow.to_string(), "Numeric overflow");
+        assert_eq!(Error::NonFinite.to_string(), "Non-finite float");
+        assert_eq!(Error::InvalidKey.to_string(), "Invalid map key");
+        assert_eq!(Error::Ext.to_string(), "Invalid extension");
+
+        let io = ::std::io::Error::other("disk");
+        assert_eq!(Error::from(io).to_string(), "IO Error");
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+        use std::str::Utf8Error;
+
+        // a fixstr holding an invalid UTF8 sequence
+        let error = ::from_bytes::<String>(&[0xa2, 0xc3, 0x28]).unwrap_err();
+
+        let mut root: &dyn Error = &error;
+        while let Some(source) = root.source() {
+            root = source;
+        }
+
+        assert!(root.downcast_ref::<Utf8Error>().is_some());
+    }
+
+    #[test]
+    fn test_decode_bin() {
+        let payload: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+
+        let mut fixture = vec![0xc6, 0x00, 0x10, 0x00, 0x00];
+        fixture.extend_from_slice(&payload);
+        fixture.push(0xc0);
+
+        let (decoded, len) = ::decode_bin(&fixture).unwrap();
+        assert_eq!(len, fixture.len() - 1);
+        assert_eq!(decoded, payload);
+
+        // copied with a single exactly-sized allocation
+        assert_eq!(decoded.capacity(), payload.len());
+
+        assert_eq!(::decode_bin(&[0xc4, 0x02, 0x01, 0x02]).unwrap(), (vec![1, 2], 4));
+        assert!(::decode_bin(&[0xc4, 0x03, 0x01]).is_err());
+        assert!(::decode_bin(&[0xa1, 0x61]).is_err());
+    }
+
+    #[test]
+    fn extract_field_test() {
+        // a map of ten fields, where every field but f4 holds a string of invalid UTF-8 that
+        // would fail if it were decoded
+        let mut fixture = vec![0x8a];
+        for i in 0..10u8 {
+            fixture.extend_from_slice(&[0xa2, 0x66, 0x30 + i]);
+
+            if i == 4 {
+                fixture.extend_from_slice(&[0xcd, 0x01, 0x00]);
+            } else {
+                fixture.extend_from_slice(&[0xa2, 0xff, 0xfe]);
+            }
+        }
+
+        assert_eq!(::extract_field::<u16>(&fixture, "f4").unwrap(), Some(256));
+        assert_eq!(::extract_field::<u16>(&fixture, "missing").unwrap(), None);
+        assert!(::extract_field::<String>(&fixture, "f5").is_err());
+
+        assert!(::extract_field::<u16>(&[0x93, 0x01, 0x02, 0x03], "f4").is_err());
+        assert!(::extract_field::<u16>(&fixture[..10], "f4").is_err());
+    }
+
+    #[test]
+    fn test_to_hex_into() {
+        let value = (1u8, "hi", vec![0xffu16]);
+
+        let mut hex = String::new();
+        ::to_hex_into(&mut hex, &value).unwrap();
+
+        let expected: String =
+            ::to_bytes(&value).unwrap().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        assert_eq!(hex, expected);
+        assert_eq!(hex, "9301a2686991ccff");
+    }
+
+    #[test]
+    fn test_to_vec_with_capacity() {
+        let value = ("record", 42u32, vec![1u8, 2, 3]);
+
+        let bytes = ::to_vec_with_capacity(&value, 64).unwrap();
+
+        assert_eq!(bytes, ::to_bytes(&value).unwrap());
+
+        // the value fits, so the buffer keeps exactly the capacity it was made with
+        assert!(bytes.len() < 64);
+        assert_eq!(bytes.capacity(), 64);
+    }
+
+    #[test]
+    fn test_verify_canonical() {
+        use std::collections::BTreeMap;
+        use error::Error;
+
+        let reason = |bytes: &[u8]| match ::verify_canonical(bytes) {
+            Err(Error::NonCanonical(offset, reason)) => (offset, reason),
+            other => panic!("expected a canonical violation, got {:?}", other),
+        };
+
+        let mut map = BTreeMap::new();
+        map.insert("b".to_string(), vec![1000u32, 7]);
+        map.insert("a".to_string(), vec![]);
+
+        let bytes = ::to_bytes_with_config(&map, ::SerializerConfig::canonical()).unwrap();
+        ::verify_canonical(&bytes).unwrap();
+
+        assert_eq!(reason(&[0x91, 0xcc, 0x05]), (1, "integer is not minimal"));
+        assert_eq!(reason(&[0xd1, 0x00, 0x80]), (0, "integer is not minimal"));
+        assert_eq!(reason(&[0xd9, 0x01, 0x61]), (0, "string length is not minimal"));
+        assert_eq!(reason(&[0xdc, 0x00, 0x00]), (0, "array length is not minimal"));
+        assert_eq!(reason(&[0xc7, 0x01, 0x05, 0x00]), (0, "extension length is not minimal"));
+        assert_eq!(reason(&[0x82, 0xa1, 0x62, 0xc0, 0xa1, 0x61, 0xc0]),
+                   (4, "map keys are not sorted"));
+        assert_eq!(reason(&[0x82, 0xa1, 0x61, 0xc0, 0xa1, 0x61, 0xc0]),
+                   (4, "duplicate map key"));
+        assert_eq!(reason(&[0x81, 0x91, 0xa1, 0x61, 0x90, 0xc0]), (5, "trailing data"));
+
+        assert!(::verify_canonical(&[0x92, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_measuring_depth() {
+        use config::DeserializerConfig;
+
+        let config = DeserializerConfig::new();
+
+        // {"a": [[[{"b": 1}]]]}
+        let bytes = [0x81, 0xa1, 0x61, 0x91, 0x91, 0x91, 0x81, 0xa1, 0x62, 0x01];
+        let (_, depth) =
+            ::from_bytes_measuring_depth::<::serde::de::IgnoredAny>(&bytes, config).unwrap();
+        assert_eq!(depth, 5);
+
+        let (value, depth) = ::from_bytes_measuring_depth::<u8>(&[0x07], config).unwrap();
+        assert_eq!((value, depth), (7, 0));
+
+        // the peak is the deepest point, not the total number of containers
+        let bytes = ::to_bytes((vec![1], vec![2], vec![3])).unwrap();
+        let (_, depth) =
+            ::from_bytes_measuring_depth::<(Vec<u8>, Vec<u8>, Vec<u8>)>(&bytes, config).unwrap();
+        assert_eq!(depth, 2);
+    }
+
+    #[test]
+    fn test_nonzero() {
+        use std::num::{NonZeroI32, NonZeroU64, NonZeroU8};
+
+        test_through(NonZeroU8::new(200).unwrap(), &[0xcc, 0xc8]);
+        test_through(NonZeroI32::new(-70000).unwrap(), &[0xd2, 0xff, 0xfe, 0xee, 0x90]);
+        test_through(NonZeroU64::new(5).unwrap(), &[0x05]);
+
+        assert_eq!(::to_bytes(NonZeroU64::new(5).unwrap()).unwrap(),
+                   ::to_bytes(5u64).unwrap());
+
+        // zero is rejected rather than wrapped
+        assert!(::from_bytes::<NonZeroU8>(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn test_integer_repr_enum() {
+        use serde::{Serializer, Deserializer, Deserialize};
+        use serde::de::Error;
+
+        // what serde_repr derives for a #[repr(i16)] enum: the bare discriminant
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        enum Status {
+            Failed = -2,
+            Ok = 0,
+            Busy = 300,
+        }
+
+        impl Serialize for Status {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_i16(*self as i16)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for Status {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Status, D::Error> {
+                match i16::deserialize(deserializer)? {
+                    -2 => Ok(Status::Failed),
+                    0 => Ok(Status::Ok),
+                    300 => Ok(Status::Busy),
+                    other => Err(D::Error::custom(format_args!("invalid value: {}", other))),
+                }
+            }
+        }
+
+        test_through(Status::Failed, &[0xfe]);
+        test_through(Status::Ok, &[0x00]);
+        test_through(Status::Busy, &[0xcd, 0x01, 0x2c]);
+
+        test_through(vec![Status::Busy, Status::Failed], &[0x92, 0xcd, 0x01, 0x2c, 0xfe]);
+
+        // the same discriminant written in a wider form still dispatches
+        assert_eq!(::from_bytes::<Status>(&[0xd1, 0x01, 0x2c]).unwrap(), Status::Busy);
+        assert!(::from_bytes::<Status>(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        use error::Error;
+
+        let malformed = |result: Result<(), Error>| match result {
+            Err(Error::Malformed(offset, reason)) => (offset, reason),
+            other => panic!("expected Malformed, got {:?}", other),
+        };
+
+        let bytes = ::to_bytes((vec!["a", "bc"], 300u16, ())).unwrap();
+        ::validate(&bytes).unwrap();
+
+        // the second string is cut short
+        assert_eq!(malformed(::validate(&bytes[..6])), (4, "truncated value"));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0x01);
+        assert_eq!(malformed(::validate(&trailing)), (bytes.len(), "trailing data"));
+        ::validate_stream(&trailing).unwrap();
+
+        assert_eq!(malformed(::validate(&[0x92, 0x01, 0xc1])), (2, "invalid type byte"));
+        assert_eq!(malformed(::validate(&[0x91, 0xa2, 0x61, 0xff])),
+                   (1, "invalid UTF-8 in string"));
+        assert_eq!(malformed(::validate(&[])), (0, "truncated value"));
+
+        ::validate_stream(&[]).unwrap();
+        // the array's second element is missing
+        assert_eq!(malformed(::validate_stream(&[0x01, 0x02, 0x92, 0x03])),
+                   (4, "truncated value"));
+        assert_eq!(Error::Malformed(2, "truncated value").to_string(),
+                   "Malformed value at byte 2: truncated value");
+    }
+
+    #[test]
+    fn test_nested() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Envelope<'a> {
+            version: u8,
+            #[serde(borrow)]
+            body: &'a str,
+        }
+
+        let value = Envelope {
+            version: 2,
+            body: "hi",
+        };
+
+        let bytes = ::to_vec_nested(&value).unwrap();
+        let inner = ::to_bytes(&value).unwrap();
+        assert_eq!(&bytes[..2], &[0xc4, inner.len() as u8]);
+        assert_eq!(&bytes[2..], &inner[..]);
+
+        assert_eq!(::from_nested::<Envelope>(&bytes).unwrap(), value);
+
+        // a payload long enough for a bin16 header
+        let long = vec![7u32; 300];
+        let bytes = ::to_vec_nested(&long).unwrap();
+        assert_eq!(bytes[0], 0xc5);
+        assert_eq!(::from_nested::<Vec<u32>>(&bytes).unwrap(), long);
+
+        // the bare document, or a bin with bytes after it, is refused
+        assert!(::from_nested::<Envelope>(&inner).is_err());
+        let mut trailing = ::to_vec_nested(&value).unwrap();
+        trailing.push(0xc0);
+        match ::from_nested::<Envelope>(&trailing) {
+            Err(::error::Error::TrailingData) => {}
+            other => panic!("expected TrailingData, got {:?}", other),
+        }
+
+        // so is a payload with bytes after the nested value
+        let mut bytes = vec![0xc4, inner.len() as u8 + 1];
+        bytes.extend(&inner);
+        bytes.push(0xc0);
+        match ::from_nested::<Envelope>(&bytes) {
+            Err(::error::Error::TrailingData) => {}
+            other => panic!("expected TrailingData, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_to_vec_from_slice() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Point<'a> {
+            x: i32,
+            label: &'a str,
+        }
+
+        let point = Point { x: -3, label: "p" };
+
+        let bytes = ::to_vec(&point).unwrap();
+        assert_eq!(bytes, ::to_bytes(&point).unwrap());
+        assert_eq!(::from_slice::<Point>(&bytes).unwrap(), point);
+
+        // unsized values work too
+        assert_eq!(::to_vec("abc").unwrap(), &[0xa3, 0x61, 0x62, 0x63]);
+        assert_eq!(::to_vec(&[1u8, 2][..]).unwrap(), &[0x92, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_to_writer() {
+        use std::io::{self, Write};
+
+        use error::Error;
+
+        let mut bytes = vec![];
+        ::to_writer(&mut bytes, &(1u8, "ab")).unwrap();
+        assert_eq!(bytes, ::to_bytes((1u8, "ab")).unwrap());
+
+        struct Sink {
+            written: usize,
+            flushed: bool,
+            capacity: usize,
+        }
+
+        impl Write for Sink {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.written + buf.len() > self.capacity {
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, "full"));
+                }
+
+                self.written += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed = true;
+                Ok(())
+            }
+        }
+
+        let mut sink = Sink {
+            written: 0,
+            flushed: false,
+            capacity: 16,
+        };
+        ::to_writer(&mut sink, "hello").unwrap();
+        assert_eq!(sink.written, 6);
+        assert!(sink.flushed);
+
+        // the writer's error comes back as is
+        let mut full = Sink {
+            written: 0,
+            flushed: false,
+            capacity: 4,
+        };
+        match ::to_writer(&mut full, "hello") {
+            Err(Error::Io(ref error)) if error.kind() == io::ErrorKind::WriteZero => {}
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_read() {
+        use std::io::{self, Read};
+
+        use config::DeserializerConfig;
+        use error::Error;
+
+        // hands out at most three bytes per read, like a slow socket
+        struct Trickle<'a>(&'a [u8]);
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(self.0.len()).min(3);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let value = (vec!["a long enough string".to_string()], 70000u32);
+        let mut bytes = ::to_bytes(&value).unwrap();
+        bytes.extend(::to_bytes("next").unwrap());
+
+        // values come out one at a time, leaving the rest of the source unread
+        let mut source = Trickle(&bytes);
+        let decoded: (Vec<String>, u32) = ::from_read(&mut source).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(::from_read::<_, String>(&mut source).unwrap(), "next");
+
+        match ::from_read::<_, String>(&mut source) {
+            Err(Error::EndOfStream) => {}
+            other => panic!("expected EndOfStream, got {:?}", other),
+        }
+
+        // a header announcing more than the source holds doesn't allocate it up front
+        let huge = [0xdb, 0xff, 0xff, 0xff, 0xff, 0x61];
+        match ::from_read::<_, String>(&huge[..]) {
+            Err(Error::EndOfStream) => {}
+            other => panic!("expected EndOfStream, got {:?}", other),
+        }
+
+        // limits apply to streamed input too
+        let config = DeserializerConfig::new().max_str_len(8);
+        match ::from_read_with_config::<_, (Vec<String>, u32)>(Trickle(&bytes), config) {
+            Err(Error::TooBig) => {}
+            other => panic!("expected TooBig, got {:?}", other),
+        }
+        assert_eq!(::from_read_with_config::<_, String>(Trickle(&bytes[bytes.len() - 5..]),
+                                                        config)
+                       .unwrap(),
+                   "next");
+    }
+
+    #[test]
+    fn test_value_len() {
+        assert_eq!(::value_len(&[0x2a, 0xff]).unwrap(), 1);
+        assert_eq!(::value_len(&[0xcd, 0x01, 0x00]).unwrap(), 3);
+
+        let nested = ::to_bytes((vec![vec![1u8, 2], vec![]], "abc")).unwrap();
+        let mut trailing = nested.clone();
+        trailing.push(0xc0);
+        assert_eq!(::value_len(&trailing).unwrap(), nested.len());
+
+        // fixext4, then ext8 with a three byte payload
+        assert_eq!(::value_len(&[0xd6, 0x01, 0x00, 0x00, 0x00, 0x00]).unwrap(), 6);
+        assert_eq!(::value_len(&[0xc7, 0x03, 0x05, 0x01, 0x02, 0x03]).unwrap(), 6);
+
+        assert!(::value_len(&nested[..nested.len() - 1]).is_err());
+        assert!(::value_len(&[]).is_err());
+    }
+
+    #[test]
+    fn test_scan_values() {
+        let mut bytes = ::to_bytes("first").unwrap();
+        bytes.extend(::to_bytes(vec![1u32, 70000, 3]).unwrap());
+        bytes.extend(::to_bytes(()).unwrap());
+
+        let ranges: Vec<_> = ::scan_values(&bytes).map(Result::unwrap).collect();
+        assert_eq!(ranges, vec![0..6, 6..14, 14..15]);
+
+        assert_eq!(::from_bytes::<Vec<u32>>(&bytes[ranges[1].clone()]).unwrap(),
+                   vec![1, 70000, 3]);
+
+        // a truncated value ends the scan with an error
+        let mut values = ::scan_values(&bytes[..10]);
+        assert_eq!(values.next().unwrap().unwrap(), 0..6);
+        assert!(values.next().unwrap().is_err());
+        assert!(values.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_values_recovering() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Record {
+            id: u32,
+            level: String,
+        }
+
+        let record = |id| Record { id, level: "info".into() };
+
+        let mut bytes = ::to_bytes(record(1)).unwrap();
+        let damaged = bytes.len();
+        bytes.extend(::to_bytes(record(2)).unwrap());
+        bytes.extend(::to_bytes(record(3)).unwrap());
+
+        // the second record's map header becomes a reserved type byte
+        bytes[damaged] = 0xc1;
+
+        let mut recovered = vec![];
+        let mut failures = vec![];
+
+        for range in ::scan_values_recovering(&bytes) {
+            match range {
+                Ok(range) => {
+                    if let Ok(record) = ::from_bytes::<Record>(&bytes[range]) {
+                        recovered.push(record);
+                    }
+                }
+                Err(::error::Error::Malformed(position, _)) => failures.push(position),
+                Err(error) => panic!("unexpected error {:?}", error),
+            }
+        }
+
+        assert_eq!(recovered, vec![record(1), record(3)]);
+        assert_eq!(failures, vec![damaged]);
+
+        // the plain scan gives up at the damaged record
+        assert_eq!(::scan_values(&bytes).filter(Result::is_ok).count(), 1);
+
+        // garbage running to the end of the slice is reported once
+        let mut tail = ::to_bytes(record(1)).unwrap();
+        tail.extend(&[0xc1, 0xc1, 0xc1]);
+        let ranges: Vec<_> = ::scan_values_recovering(&tail).collect();
+        assert_eq!(ranges.len(), 2);
+        assert!(ranges[1].is_err());
+
+        // records longer than the lookahead are still picked up after garbage
+        let long = Record { id: 4, level: "x".repeat(200) };
+        let mut bytes = vec![0xc1];
+        bytes.extend(::to_bytes(&long).unwrap());
+        bytes.extend(::to_bytes(&long).unwrap());
+        let ranges: Vec<_> = ::scan_values_recovering(&bytes).collect();
+        assert_eq!(ranges.len(), 3);
+        assert!(ranges[0].is_err());
+        let last = ranges[2].as_ref().unwrap().clone();
+        assert_eq!(::from_bytes::<Record>(&bytes[last]).unwrap(), long);
+    }
 }
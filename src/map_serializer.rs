@@ -6,32 +6,69 @@
 #[cfg(feature = "alloc")]
 use alloc::Vec;
 
+use std::mem;
+
 use serde::ser::{Serialize, SerializeMap, SerializeStruct, SerializeStructVariant};
 
 use byteorder::{ByteOrder, BigEndian};
 
 use ser::Serializer;
 
+use config::SerializerConfig;
+use intern::StringTable;
+use none_probe;
+
 use defs::*;
 use error::Error;
+use write::{Write, Forward};
 
-pub struct MapSerializer<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> {
+pub struct MapSerializer<'a, F: 'a + Write> {
     count: usize,
     size: Option<usize>,
+    patch_position: Option<usize>,
+    skip_none: bool,
     buffer: Vec<u8>,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
     output: &'a mut F,
+    config: SerializerConfig,
+    strings: &'a mut StringTable,
+    positional: bool,
+    presorted: bool,
+    last_key: Option<Vec<u8>>,
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> MapSerializer<'a, F> {
-    pub fn new(output: &'a mut F) -> MapSerializer<'a, F> {
+impl<'a, F: 'a + Write> MapSerializer<'a, F> {
+    pub(crate) fn new(output: &'a mut F,
+                      config: SerializerConfig,
+                      strings: &'a mut StringTable)
+                      -> MapSerializer<'a, F> {
         MapSerializer {
             count: 0,
             size: None,
+            patch_position: None,
+            skip_none: false,
             buffer: vec![],
-            output: output,
+            entries: vec![],
+            output,
+            config,
+            strings,
+            positional: false,
+            presorted: false,
+            last_key: None,
         }
     }
 
+    /// Serialize a struct as an array of its field values, leaving out the field names.
+    pub fn positional(&mut self) {
+        self.positional = true;
+    }
+
+    /// Write entries in the order they arrive under `sort_map_keys`, checking that they are
+    /// already sorted, if the configuration allows it.
+    pub fn presorted(&mut self) {
+        self.presorted = self.config.assume_sorted_keys;
+    }
+
     pub fn hint_size(&mut self, size: Option<usize>) -> Result<(), Error> {
         self.size = size;
 
@@ -43,12 +80,33 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> MapSerializer<'a, F> {
         }
     }
 
+    /// Leave out struct fields that are None. The map's length is then unknown until it ends, so
+    /// a placeholder header is written if the output can patch it, and the map is buffered if
+    /// not.
+    pub fn skip_none_fields(&mut self) -> Result<(), Error> {
+        self.skip_none = true;
+
+        if let (true, Some(position)) = (self.config.backpatch_sequences,
+                                         self.output.position()) {
+            // write a placeholder header now and fill in the length at the end
+            self.patch_position = Some(position);
+            self.output.write(&[MAP32, 0, 0, 0, 0])
+        } else {
+            Ok(())
+        }
+    }
+
     fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
+        let key = !self.expecting_value();
         self.count += 1;
 
-        if self.should_serialize_directly() {
+        if key && self.checking_order() {
+            self.serialize_checked_key(value)
+        } else if self.sorting() {
+            self.serialize_into_entry(value)
+        } else if self.should_serialize_directly() {
             self.serialize_directly(value)
         } else {
             self.serialize_into_buffer(value)
@@ -57,36 +115,105 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> MapSerializer<'a, F> {
 
     fn finish(mut self) -> Result<(), Error> {
         if let Some(size) = self.size {
-            self.check_item_count_matches_size(size * 2)?;
-            Ok(())
+            self.check_item_count_matches_size(size * self.entry_width())?;
+        } else if let Some(position) = self.patch_position {
+            let count = self.get_item_count()?;
+
+            if count > MAX_MAP32 {
+                return Err(Error::TooBig);
+            }
+
+            let mut buf = [0; U32_BYTES];
+            BigEndian::write_u32(&mut buf, count as u32);
+            self.output.patch(position + 1, &buf)?;
         } else {
             let count = self.get_item_count()?;
             self.output_map_header(count)?;
-            (self.output)(&*self.buffer)
+            self.output.write(&self.buffer)?;
+        }
+
+        if self.sorting() {
+            self.output_sorted_entries()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn output_sorted_entries(&mut self) -> Result<(), Error> {
+        let mut entries = mem::take(&mut self.entries);
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if entries.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(Error::InvalidKey);
         }
+
+        for (key, value) in entries {
+            self.output.write(&key)?;
+            self.output.write(&value)?;
+        }
+
+        Ok(())
     }
 
     fn output_map_header(&mut self, size: usize) -> Result<(), Error> {
-        if size <= MAX_FIXMAP {
-            (self.output)(&[size as u8 | FIXMAP_MASK])
+        if self.positional {
+            self.output_array_header(size)
+        } else if size <= MAX_FIXMAP {
+            self.output.write(&[size as u8 | FIXMAP_MASK])
         } else if size <= MAX_MAP16 {
             let mut buf = [MAP16; U16_BYTES + 1];
             BigEndian::write_u16(&mut buf[1..], size as u16);
-            (self.output)(&buf)
+            self.output.write(&buf)
         } else if size <= MAX_MAP32 {
             let mut buf = [MAP32; U32_BYTES + 1];
             BigEndian::write_u32(&mut buf[1..], size as u32);
-            (self.output)(&buf)
+            self.output.write(&buf)
+        } else {
+            Err(Error::TooBig)
+        }
+    }
+
+    fn output_array_header(&mut self, size: usize) -> Result<(), Error> {
+        if size <= MAX_FIXARRAY {
+            self.output.write(&[size as u8 | FIXARRAY_MASK])
+        } else if size <= MAX_ARRAY16 {
+            let mut buf = [ARRAY16; U16_BYTES + 1];
+            BigEndian::write_u16(&mut buf[1..], size as u16);
+            self.output.write(&buf)
+        } else if size <= MAX_ARRAY32 {
+            let mut buf = [ARRAY32; U32_BYTES + 1];
+            BigEndian::write_u32(&mut buf[1..], size as u32);
+            self.output.write(&buf)
         } else {
             Err(Error::TooBig)
         }
     }
 
+    fn entry_width(&self) -> usize {
+        if self.positional { 1 } else { 2 }
+    }
+
+    fn expecting_value(&self) -> bool {
+        self.count % 2 != 0
+    }
+
     fn get_item_count(&self) -> Result<usize, Error> {
-        if self.count % 1 != 0 {
+        if self.count % self.entry_width() != 0 {
             Err(Error::BadLength)
         } else {
-            Ok(self.count / 2)
+            Ok(self.count / self.entry_width())
+        }
+    }
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+        where T: ?Sized + Serialize
+    {
+        if self.positional {
+            self.serialize_element(value)
+        } else if self.skip_none && none_probe::is_none(value) {
+            Ok(())
+        } else {
+            self.serialize_entry(key, value)
         }
     }
 
@@ -98,43 +225,116 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> MapSerializer<'a, F> {
         }
     }
 
+    fn sorting(&self) -> bool {
+        self.config.sort_map_keys && !self.positional && !self.presorted
+    }
+
+    fn checking_order(&self) -> bool {
+        self.config.sort_map_keys && !self.positional && self.presorted
+    }
+
     fn should_serialize_directly(&mut self) -> bool {
-        self.size.is_some()
+        self.size.is_some() || self.patch_position.is_some()
     }
 
     fn serialize_into_buffer<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        let mut target = Serializer::new(|bytes| {
-            self.buffer.extend_from_slice(bytes);
-            Ok(())
-        });
+        let buffer = &mut self.buffer;
 
-        value.serialize(&mut target)
+        Serializer::serialize_nested(|bytes: &[u8]| {
+                                         buffer.extend_from_slice(bytes);
+                                         Ok(())
+                                     },
+                                     self.config,
+                                     self.strings,
+                                     value)
     }
 
-    fn serialize_directly<T>(&mut self, value: &T) -> Result<(), Error>
+    fn serialize_into_entry<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        let mut target = Serializer::new(|bytes| (self.output)(bytes));
+        // entries are written out of order once sorted, so keep their strings out of the table
+        let mut config = self.config;
+        config.intern_strings = false;
+
+        let mut bytes = vec![];
+
+        Serializer::serialize_nested(|buf: &[u8]| {
+                                         bytes.extend_from_slice(buf);
+                                         Ok(())
+                                     },
+                                     config,
+                                     self.strings,
+                                     value)?;
 
-        value.serialize(&mut target)
+        if self.expecting_value() {
+            self.entries.push((bytes, vec![]));
+        } else if let Some(entry) = self.entries.last_mut() {
+            entry.1 = bytes;
+        }
+
+        Ok(())
+    }
+
+    fn serialize_checked_key<T>(&mut self, key: &T) -> Result<(), Error>
+        where T: ?Sized + Serialize
+    {
+        let mut bytes = vec![];
+
+        Serializer::serialize_nested(|buf: &[u8]| {
+                                         bytes.extend_from_slice(buf);
+                                         Ok(())
+                                     },
+                                     self.config,
+                                     self.strings,
+                                     key)?;
+
+        if let Some(ref last) = self.last_key {
+            if *last >= bytes {
+                return Err(Error::InvalidKey);
+            }
+        }
+
+        if self.should_serialize_directly() {
+            self.output.write(&bytes)?;
+        } else {
+            self.buffer.extend_from_slice(&bytes);
+        }
+
+        self.last_key = Some(bytes);
+
+        Ok(())
+    }
+
+    fn serialize_directly<T>(&mut self, value: &T) -> Result<(), Error>
+        where T: ?Sized + Serialize
+    {
+        Serializer::serialize_nested(Forward(&mut *self.output), self.config, self.strings, value)
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeMap for MapSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeMap for MapSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
+        if self.expecting_value() {
+            return Err(Error::Other("Map key serialized where a value was expected".into()));
+        }
+
         MapSerializer::serialize_element(self, key)
     }
 
     fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
+        if !self.expecting_value() {
+            return Err(Error::Other("Map value serialized where a key was expected".into()));
+        }
+
         MapSerializer::serialize_element(self, value)
     }
 
@@ -143,14 +343,14 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeMap for MapSerializ
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeStruct for MapSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeStruct for MapSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        MapSerializer::serialize_entry(self, key, value)
+        MapSerializer::serialize_field(self, key, value)
     }
 
     fn end(self) -> Result<(), Error> {
@@ -158,7 +358,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeStruct for MapSeria
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeStructVariant
+impl<'a, F: 'a + Write> SerializeStructVariant
     for MapSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
@@ -166,7 +366,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeStructVariant
     fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        MapSerializer::serialize_entry(self, key, value)
+        MapSerializer::serialize_field(self, key, value)
     }
 
     fn end(self) -> Result<(), Error> {
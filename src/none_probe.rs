@@ -0,0 +1,154 @@
+//! A serializer that only finds out whether a value is None.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+use serde::ser::{Serialize, Serializer, Impossible};
+
+use error::Error;
+
+/// Check whether a value serializes as None, without writing anything.
+pub fn is_none<T>(value: &T) -> bool
+    where T: ?Sized + Serialize
+{
+    value.serialize(NoneProbe).unwrap_or(false)
+}
+
+/// Answers true for None and false for any other value. Compound values stop the probe with an
+/// error as soon as they start, so their contents are never visited.
+struct NoneProbe;
+
+impl Serializer for NoneProbe {
+    type Ok = bool;
+    type Error = Error;
+    type SerializeSeq = Impossible<bool, Error>;
+    type SerializeTuple = Impossible<bool, Error>;
+    type SerializeTupleStruct = Impossible<bool, Error>;
+    type SerializeTupleVariant = Impossible<bool, Error>;
+    type SerializeMap = Impossible<bool, Error>;
+    type SerializeStruct = Impossible<bool, Error>;
+    type SerializeStructVariant = Impossible<bool, Error>;
+
+    fn serialize_none(self) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn serialize_some<T>(self, _: &T) -> Result<bool, Error>
+        where T: ?Sized + Serialize
+    {
+        Ok(false)
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_char(self, _: char) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_str(self, _: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_unit(self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str)
+                              -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn serialize_newtype_struct<T>(self, _: &'static str, _: &T) -> Result<bool, Error>
+        where T: ?Sized + Serialize
+    {
+        Ok(false)
+    }
+
+    fn serialize_newtype_variant<T>(self, _: &'static str, _: u32, _: &'static str, _: &T)
+                                    -> Result<bool, Error>
+        where T: ?Sized + Serialize
+    {
+        Ok(false)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize)
+                              -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize)
+                               -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(Error::BadType)
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize)
+                                -> Result<Self::SerializeStructVariant, Error> {
+        Err(Error::BadType)
+    }
+}
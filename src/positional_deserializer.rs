@@ -0,0 +1,65 @@
+//! The visitor that decodes an array as the fields of a struct, by position.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+use serde::de::{MapAccess, DeserializeSeed, Deserialize, IgnoredAny, IntoDeserializer};
+use serde::de::value::StrDeserializer;
+
+use de::Deserializer;
+
+use error::Error;
+use read::Read;
+
+pub struct PositionalDeserializer<'de: 'a, 'a, R: 'a + Read<'de>> {
+    de: &'a mut Deserializer<'de, R>,
+    fields: &'static [&'static str],
+    count: usize,
+    index: usize,
+}
+
+impl<'de, 'a, R: Read<'de>> PositionalDeserializer<'de, 'a, R> {
+    pub fn new(de: &'a mut Deserializer<'de, R>,
+               fields: &'static [&'static str],
+               count: usize)
+               -> PositionalDeserializer<'de, 'a, R> {
+        PositionalDeserializer {
+            de,
+            fields,
+            count,
+            index: 0,
+        }
+    }
+}
+
+impl<'de, 'a, R: Read<'de>> MapAccess<'de> for PositionalDeserializer<'de, 'a, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+        where K: DeserializeSeed<'de>
+    {
+        if self.index < self.count && self.index < self.fields.len() {
+            let key: StrDeserializer<Error> = self.fields[self.index].into_deserializer();
+            return seed.deserialize(key).map(Some);
+        }
+
+        // elements past the last field were added by a newer writer, so skip them
+        while self.index < self.count {
+            IgnoredAny::deserialize(&mut *self.de)?;
+            self.index += 1;
+        }
+
+        Ok(None)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+        where V: DeserializeSeed<'de>
+    {
+        self.index += 1;
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.count.min(self.fields.len()).saturating_sub(self.index))
+    }
+}
@@ -0,0 +1,407 @@
+//! Values captured as raw MessagePack bytes, to be decoded or inspected later.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::{Vec, String};
+
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use std::cell::Cell;
+use std::fmt;
+
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer, Visitor};
+
+use defs::{FIXARRAY_MASK, NIL};
+use error::Error;
+use scan;
+
+/// The newtype struct name the deserializer recognizes as a request to capture raw bytes.
+pub(crate) const RAW_MESSAGE_NAME: &str = "$corepack::RawMessage";
+
+/// A single MessagePack value kept as its encoded bytes.
+///
+/// Deserializing into a RawMessage skips over the value and records the bytes it occupied,
+/// without building anything from them. Call `decode` once the concrete type is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawMessage(Vec<u8>);
+
+impl RawMessage {
+    /// The encoded bytes of the value.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Decode the captured value as V, with the default configuration.
+    pub fn decode<'a, V>(&'a self) -> Result<V, Error>
+        where V: Deserialize<'a>
+    {
+        ::from_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawMessage {
+    fn deserialize<D>(deserializer: D) -> Result<RawMessage, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_newtype_struct(RAW_MESSAGE_NAME, RawMessageVisitor)
+    }
+}
+
+struct RawMessageVisitor;
+
+impl<'de> Visitor<'de> for RawMessageVisitor {
+    type Value = RawMessage;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a value captured by the corepack deserializer")
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<RawMessage, E>
+        where E: de::Error
+    {
+        Ok(RawMessage(bytes))
+    }
+}
+
+/// A value decoded from a map, along with the keys the map actually contained.
+///
+/// This tells a field that was left out apart from one that was present with its default value.
+/// Only string keys are recorded, and the value must have been encoded as a map.
+///
+/// The map is captured as a `RawMessage` and then decoded on its own, with the default
+/// `DeserializerConfig` rather than the one the outer value is being decoded with. Limits and
+/// options set on the outer deserializer only apply to the capture, which checks the map's
+/// structure but doesn't build V.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithPresence<V> {
+    value: V,
+    present: Vec<String>,
+}
+
+impl<V> WithPresence<V> {
+    /// The decoded value.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// Whether the map contained the given key.
+    pub fn is_present(&self, field: &str) -> bool {
+        self.present.iter().any(|key| key == field)
+    }
+
+    /// The string keys the map contained, in the order they appeared.
+    pub fn present(&self) -> &[String] {
+        &self.present
+    }
+
+    /// Unwrap the decoded value.
+    pub fn into_inner(self) -> V {
+        self.value
+    }
+}
+
+impl<'de, V: DeserializeOwned> Deserialize<'de> for WithPresence<V> {
+    fn deserialize<D>(deserializer: D) -> Result<WithPresence<V>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let raw = RawMessage::deserialize(deserializer)?;
+        let bytes = raw.as_bytes();
+
+        let mut present = vec![];
+        for entry in scan::Entries::new(bytes).map_err(de::Error::custom)? {
+            let (key, _) = entry.map_err(de::Error::custom)?;
+
+            if let Ok(key) = ::from_bytes::<&str>(&bytes[key]) {
+                present.push(key.into());
+            }
+        }
+
+        Ok(WithPresence {
+               value: raw.decode().map_err(de::Error::custom)?,
+               present,
+           })
+    }
+}
+
+/// An enum with a catch-all variant for variants added after it was written.
+///
+/// The catch-all variant should be declared last and marked `#[serde(skip)]`, so the variants
+/// before it keep the indices they are written with.
+pub trait FromUnknownVariant {
+    /// Build the catch-all variant from the index of the unknown variant and its encoded value.
+    /// Unit variants written as a bare index have a nil value.
+    fn from_unknown_variant(index: u32, value: RawMessage) -> Self;
+}
+
+/// Decode an enum, turning any variant past the ones it knows into its catch-all variant.
+///
+/// Use this on a field with `#[serde(deserialize_with = "corepack::raw::deserialize_or_unknown")]`.
+/// Errors decoding a known variant are still reported.
+///
+/// Like `WithPresence`, the enum is captured first and then decoded on its own with the default
+/// `DeserializerConfig`, so options set on the outer deserializer don't apply to the variant's
+/// value.
+pub fn deserialize_or_unknown<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>,
+          T: DeserializeOwned + FromUnknownVariant
+{
+    let raw = RawMessage::deserialize(deserializer)?;
+    let bytes = raw.as_bytes();
+
+    // enums are [index, value], or a bare index for unit variants
+    let (index, value) = match bytes.first() {
+        Some(&ty) if ty == 2 | FIXARRAY_MASK => {
+            let len = scan::value_len(&bytes[1..]).map_err(de::Error::custom)?;
+            (::from_bytes::<u32>(&bytes[1..1 + len]).ok(), bytes[1 + len..].to_vec())
+        }
+        _ => (::from_bytes::<u32>(bytes).ok(), vec![NIL]),
+    };
+
+    match (index, variant_count::<T>()) {
+        (Some(index), Some(count)) if index as usize >= count => {
+            Ok(T::from_unknown_variant(index, RawMessage(value)))
+        }
+        _ => raw.decode().map_err(de::Error::custom),
+    }
+}
+
+/// Find out how many variants an enum accepts, by starting to deserialize one.
+fn variant_count<T>() -> Option<usize>
+    where T: DeserializeOwned
+{
+    let count = Cell::new(None);
+    let _ = T::deserialize(VariantCounter(&count));
+
+    count.get()
+}
+
+/// A deserializer that only records the variants it's asked for, and fails everything.
+struct VariantCounter<'a>(&'a Cell<Option<usize>>);
+
+impl<'de, 'a> Deserializer<'de> for VariantCounter<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, _: V) -> Result<V::Value, Error>
+        where V: Visitor<'de>
+    {
+        Err(Error::BadType)
+    }
+
+    fn deserialize_enum<V>(self,
+                           _: &'static str,
+                           variants: &'static [&'static str],
+                           _: V)
+                           -> Result<V::Value, Error>
+        where V: Visitor<'de>
+    {
+        self.0.set(Some(variants.len()));
+        Err(Error::BadType)
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// A map whose values are left encoded until they are asked for.
+///
+/// This indexes a map by its string keys without decoding any of the values, which is useful when
+/// only a few of them matter or their types depend on something else in the document.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LazyMap {
+    entries: HashMap<String, RawMessage>,
+}
+
+#[cfg(feature = "std")]
+impl LazyMap {
+    /// The captured value stored under a key.
+    pub fn get(&self, key: &str) -> Option<&RawMessage> {
+        self.entries.get(key)
+    }
+
+    /// Decode the value stored under a key as V, or return None if there is no such key.
+    pub fn decode<'a, V>(&'a self, key: &str) -> Result<Option<V>, Error>
+        where V: Deserialize<'a>
+    {
+        match self.entries.get(key) {
+            Some(raw) => raw.decode().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Unwrap the underlying map of captured values.
+    pub fn into_inner(self) -> HashMap<String, RawMessage> {
+        self.entries
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for LazyMap {
+    fn deserialize<D>(deserializer: D) -> Result<LazyMap, D::Error>
+        where D: Deserializer<'de>
+    {
+        HashMap::deserialize(deserializer).map(|entries| LazyMap { entries })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{FromUnknownVariant, LazyMap, RawMessage, WithPresence};
+
+    #[derive(Serialize)]
+    struct Document {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+        scores: BTreeMap<String, f64>,
+    }
+
+    #[test]
+    fn lazy_map_test() {
+        let mut scores = BTreeMap::new();
+        scores.insert("a".into(), 1.5);
+
+        let document = Document {
+            id: 7,
+            name: "seven".into(),
+            tags: vec!["x".into(), "y".into()],
+            scores,
+        };
+
+        let bytes = ::to_bytes(&document).unwrap();
+        let lazy: LazyMap = ::from_bytes(&bytes).unwrap();
+
+        assert_eq!(lazy.get("id").unwrap().as_bytes(), &[0x07]);
+        assert_eq!(lazy.get("tags").unwrap().as_bytes(),
+                   &[0x92, 0xa1, 0x78, 0xa1, 0x79]);
+
+        let scores: BTreeMap<String, f64> = lazy.decode("scores").unwrap().unwrap();
+        assert_eq!(scores.get("a"), Some(&1.5));
+
+        assert_eq!(lazy.decode::<String>("name").unwrap(), Some("seven".into()));
+        assert_eq!(lazy.decode::<u32>("missing").unwrap(), None);
+        assert!(lazy.decode::<u32>("name").is_err());
+    }
+
+    #[test]
+    fn raw_message_in_sequence_test() {
+        let bytes = ::to_bytes((1u8, "two", [3u8, 4])).unwrap();
+        let raws: Vec<RawMessage> = ::from_bytes(&bytes).unwrap();
+
+        assert_eq!(raws.len(), 3);
+        assert_eq!(raws[1].decode::<&str>().unwrap(), "two");
+        assert_eq!(raws[2].decode::<Vec<u8>>().unwrap(), vec![3, 4]);
+    }
+
+    #[test]
+    fn with_presence_test() {
+        #[derive(Serialize)]
+        struct Sent {
+            name: &'static str,
+            count: u32,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Received {
+            name: String,
+            #[serde(default)]
+            count: u32,
+            #[serde(default)]
+            enabled: bool,
+        }
+
+        let bytes = ::to_bytes(Sent {
+                                   name: "a",
+                                   count: 0,
+                               })
+            .unwrap();
+        let decoded: WithPresence<Received> = ::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.value(),
+                   &Received {
+                        name: "a".into(),
+                        count: 0,
+                        enabled: false,
+                    });
+
+        // count was sent with its default value, enabled wasn't sent at all
+        assert!(decoded.is_present("count"));
+        assert!(!decoded.is_present("enabled"));
+        assert_eq!(decoded.present(), &["name", "count"]);
+
+        assert!(::from_bytes::<WithPresence<Vec<u8>>>(&[0x91, 0x01]).is_err());
+    }
+
+    #[test]
+    fn unknown_variant_test() {
+        #[derive(Serialize)]
+        enum Newer {
+            A(u8),
+            B,
+            C(String),
+            D,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        enum Older {
+            A(u8),
+            B,
+            #[serde(skip)]
+            Other(u32, RawMessage),
+        }
+
+        impl FromUnknownVariant for Older {
+            fn from_unknown_variant(index: u32, value: RawMessage) -> Older {
+                Older::Other(index, value)
+            }
+        }
+
+        #[derive(Deserialize, Debug)]
+        struct Envelope {
+            #[serde(deserialize_with = "super::deserialize_or_unknown")]
+            kind: Older,
+        }
+
+        #[derive(Serialize)]
+        struct Sent {
+            kind: Newer,
+        }
+
+        let decode = |kind: Newer| {
+            let bytes = ::to_bytes(Sent { kind }).unwrap();
+            ::from_bytes::<Envelope>(&bytes).map(|envelope| envelope.kind)
+        };
+
+        assert_eq!(decode(Newer::A(4)).unwrap(), Older::A(4));
+        assert_eq!(decode(Newer::B).unwrap(), Older::B);
+
+        match decode(Newer::C("new".into())).unwrap() {
+            Older::Other(2, value) => assert_eq!(value.decode::<&str>().unwrap(), "new"),
+            other => panic!("expected Other(2, ..), got {:?}", other),
+        }
+        match decode(Newer::D).unwrap() {
+            Older::Other(3, value) => assert_eq!(value.as_bytes(), &[0xc0]),
+            other => panic!("expected Other(3, ..), got {:?}", other),
+        }
+
+        // a bad value for a known variant is still an error
+        let bytes = [0x81, 0xa4, 0x6b, 0x69, 0x6e, 0x64, 0x92, 0x00, 0xa1, 0x78];
+        assert!(::from_bytes::<Envelope>(&bytes).is_err());
+
+        // and without the helper, unknown variants fail as before
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Strict {
+            kind: Older,
+        }
+        assert!(::from_bytes::<Strict>(&::to_bytes(Sent { kind: Newer::D }).unwrap()).is_err());
+    }
+}
@@ -13,12 +13,27 @@ use error::Error;
 /// The trait used by Deserializer to read input data
 pub trait Read<'de>: private::Sealed {
     /// Reads the next len bytes of data, either by borowing or copying
-    fn input<'a>(&mut self,
+    fn input<'a>(&'a mut self,
                  len: usize,
                  scratch: &'a mut Vec<u8>)
                  -> Result<Reference<'de, 'a>, Error>;
 }
 
+/// Wrapper object around an `std::io::Read` source, such as a file or socket, that provides
+/// copied data
+#[cfg(feature = "std")]
+pub struct IoRead<R: ::std::io::Read> {
+    reader: R,
+}
+
+/// Wrapper object around an `std::io::BufRead` source that provides data straight out of its
+/// buffer, copying only values that straddle a refill
+#[cfg(feature = "std")]
+pub struct IoBufRead<R: ::std::io::BufRead> {
+    reader: R,
+    pending: usize,
+}
+
 /// Data that was copied or borrowed
 pub enum Reference<'de, 'a> {
     Borrowed(&'de [u8]),
@@ -48,13 +63,40 @@ impl<'de, 'a> Deref for Reference<'de, 'a> {
 
 impl<'de, F: FnMut(usize) -> Result<&'de [u8], Error>> BorrowRead<'de, F> {
     pub fn new(thunk: F) -> BorrowRead<'de, F> {
-        BorrowRead { thunk: thunk }
+        BorrowRead { thunk }
     }
 }
 
 impl<F: FnMut(&mut [u8]) -> Result<(), Error>> CopyRead<F> {
     pub fn new(thunk: F) -> CopyRead<F> {
-        CopyRead { thunk: thunk }
+        CopyRead { thunk }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: ::std::io::Read> IoRead<R> {
+    pub fn new(reader: R) -> IoRead<R> {
+        IoRead { reader }
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: ::std::io::BufRead> IoBufRead<R> {
+    pub fn new(reader: R) -> IoBufRead<R> {
+        IoBufRead { reader, pending: 0 }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: ::std::io::BufRead> Drop for IoBufRead<R> {
+    fn drop(&mut self) {
+        // leave the reader just past the last value, for whoever reads it next
+        self.reader.consume(self.pending);
     }
 }
 
@@ -62,14 +104,23 @@ impl<'de, F: FnMut(usize) -> Result<&'de [u8], Error>> private::Sealed for Borro
 
 impl<F: FnMut(&mut [u8]) -> Result<(), Error>> private::Sealed for CopyRead<F> {}
 
+#[cfg(feature = "std")]
+impl<R: ::std::io::Read> private::Sealed for IoRead<R> {}
+
+#[cfg(feature = "std")]
+impl<R: ::std::io::BufRead> private::Sealed for IoBufRead<R> {}
+
 impl<'de, F: FnMut(usize) -> Result<&'de [u8], Error>> Read<'de> for BorrowRead<'de, F> {
-    fn input<'a>(&mut self, len: usize, _: &'a mut Vec<u8>) -> Result<Reference<'de, 'a>, Error> {
+    fn input<'a>(&'a mut self,
+                 len: usize,
+                 _: &'a mut Vec<u8>)
+                 -> Result<Reference<'de, 'a>, Error> {
         Ok(Reference::Borrowed((self.thunk)(len)?))
     }
 }
 
 impl<'de, F: FnMut(&mut [u8]) -> Result<(), Error>> Read<'de> for CopyRead<F> {
-    fn input<'a>(&mut self,
+    fn input<'a>(&'a mut self,
                  len: usize,
                  scratch: &'a mut Vec<u8>)
                  -> Result<Reference<'de, 'a>, Error> {
@@ -79,6 +130,56 @@ impl<'de, F: FnMut(&mut [u8]) -> Result<(), Error>> Read<'de> for CopyRead<F> {
     }
 }
 
+#[cfg(feature = "std")]
+impl<'de, R: ::std::io::Read> Read<'de> for IoRead<R> {
+    fn input<'a>(&'a mut self,
+                 len: usize,
+                 scratch: &'a mut Vec<u8>)
+                 -> Result<Reference<'de, 'a>, Error> {
+        use std::io::Read;
+
+        // read_to_end retries short reads, and only grows the buffer as data actually arrives,
+        // so a bogus length can't make us allocate more than the input holds
+        scratch.clear();
+        (&mut self.reader).take(len as u64).read_to_end(scratch)?;
+
+        if scratch.len() < len {
+            return Err(Error::EndOfStream);
+        }
+
+        Ok(Reference::Copied(scratch))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, R: ::std::io::BufRead> Read<'de> for IoBufRead<R> {
+    fn input<'a>(&'a mut self,
+                 len: usize,
+                 scratch: &'a mut Vec<u8>)
+                 -> Result<Reference<'de, 'a>, Error> {
+        use std::io::Read;
+
+        // the bytes handed out last time are only released now that they're no longer borrowed
+        self.reader.consume(self.pending);
+        self.pending = 0;
+
+        if self.reader.fill_buf()?.len() >= len {
+            // the buffer is already full, so this hands it out again without reading
+            self.pending = len;
+            return Ok(Reference::Copied(&self.reader.fill_buf()?[..len]));
+        }
+
+        scratch.clear();
+        (&mut self.reader).take(len as u64).read_to_end(scratch)?;
+
+        if scratch.len() < len {
+            return Err(Error::EndOfStream);
+        }
+
+        Ok(Reference::Copied(scratch))
+    }
+}
+
 mod private {
     /// Keeps users from directly implementing the Read trait
     pub trait Sealed {}
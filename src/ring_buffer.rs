@@ -0,0 +1,172 @@
+//! A bounded ring buffer that serializer output can be written into.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+
+use serde::Serialize;
+
+use ser::Serializer;
+
+use error::Error;
+
+/// A fixed-capacity byte queue for feeding serializer output into a bounded buffer.
+///
+/// Writes that do not fit fail with `Error::WouldBlock` instead of growing the buffer, so the
+/// caller can drain it (for instance into a socket) and try again.
+pub struct RingBuffer {
+    buffer: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    /// Create a new ring buffer that holds at most `capacity` bytes.
+    pub fn new(capacity: usize) -> RingBuffer {
+        RingBuffer {
+            buffer: vec![0; capacity],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// The maximum number of bytes this buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The number of bytes waiting to be drained.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no bytes waiting to be drained.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of bytes that can be written before the buffer is full.
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len
+    }
+
+    /// Append bytes to the buffer. Either all of the bytes are written, or none of them are and
+    /// `Error::WouldBlock` is returned.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() > self.remaining() {
+            return Err(Error::WouldBlock);
+        }
+
+        let capacity = self.capacity();
+        let end = (self.start + self.len) % capacity.max(1);
+        let first = bytes.len().min(capacity - end);
+
+        self.buffer[end..end + first].copy_from_slice(&bytes[..first]);
+        self.buffer[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+
+        self.len += bytes.len();
+
+        Ok(())
+    }
+
+    /// Serialize a value into the buffer.
+    ///
+    /// If serialization fails, including with `Error::WouldBlock`, anything the value wrote is
+    /// discarded so that the same value can be serialized again after draining.
+    pub fn serialize<V>(&mut self, value: V) -> Result<(), Error>
+        where V: Serialize
+    {
+        let len = self.len;
+
+        let result = {
+            let mut ser = Serializer::new(|bytes: &[u8]| self.write(bytes));
+            value.serialize(&mut ser)
+        };
+
+        if result.is_err() {
+            self.len = len;
+        }
+
+        result
+    }
+
+    /// Move up to `buf.len()` bytes out of the buffer, returning how many were moved.
+    pub fn drain(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.len);
+        let first = count.min(self.capacity() - self.start);
+
+        buf[..first].copy_from_slice(&self.buffer[self.start..self.start + first]);
+        buf[first..count].copy_from_slice(&self.buffer[..count - first]);
+
+        self.len -= count;
+        self.start = if self.len == 0 {
+            0
+        } else {
+            (self.start + count) % self.capacity()
+        };
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use error::Error;
+
+    use super::RingBuffer;
+
+    fn drain_all(ring: &mut RingBuffer, out: &mut Vec<u8>) {
+        let mut buf = [0; 5];
+        loop {
+            let count = ring.drain(&mut buf);
+            if count == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..count]);
+        }
+    }
+
+    #[test]
+    fn would_block_and_resume_test() {
+        let mut ring = RingBuffer::new(16);
+        let mut out = vec![];
+
+        ring.serialize("Hello World!").unwrap();
+
+        match ring.serialize(vec![1u8, 2, 3, 4, 5]) {
+            Err(Error::WouldBlock) => {}
+            other => panic!("expected WouldBlock, got {:?}", other),
+        }
+
+        // nothing from the failed value was left behind
+        assert_eq!(ring.len(), 13);
+
+        drain_all(&mut ring, &mut out);
+        assert!(ring.is_empty());
+
+        ring.serialize(vec![1u8, 2, 3, 4, 5]).unwrap();
+        drain_all(&mut ring, &mut out);
+
+        let mut expected = ::to_bytes("Hello World!").unwrap();
+        expected.extend(::to_bytes(vec![1u8, 2, 3, 4, 5]).unwrap());
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn wrap_around_test() {
+        let mut ring = RingBuffer::new(8);
+        let mut buf = [0; 8];
+
+        ring.write(&[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(ring.drain(&mut buf[..4]), 4);
+
+        ring.write(&[7, 8, 9, 10, 11, 12]).unwrap();
+        assert_eq!(ring.remaining(), 0);
+
+        assert_eq!(ring.drain(&mut buf), 8);
+        assert_eq!(buf, [5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+}
@@ -0,0 +1,471 @@
+//! Routines that walk encoded values without decoding them.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::str;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use defs::*;
+use error::Error;
+
+/// Read a big-endian length field of the given width at a position.
+fn read_length(bytes: &[u8], position: usize, width: usize) -> Result<usize, Error> {
+    let field = bytes.get(position..position + width).ok_or(Error::EndOfStream)?;
+
+    Ok(match width {
+        1 => field[0] as usize,
+        U16_BYTES => BigEndian::read_u16(field) as usize,
+        _ => BigEndian::read_u32(field) as usize,
+    })
+}
+
+/// Work out the header of the value at a position: how many bytes the header occupies, how many
+/// payload bytes follow it, and how many nested values follow the payload.
+pub(crate) fn header(bytes: &[u8], position: usize) -> Result<(usize, usize, usize), Error> {
+    let ty = *bytes.get(position).ok_or(Error::EndOfStream)?;
+    let at = position + 1;
+
+    Ok(match ty {
+        v if POS_FIXINT.contains(v) || NEG_FIXINT.contains(v) => (1, 0, 0),
+        v if FIXMAP.contains(v) => (1, 0, (v & !FIXMAP_MASK) as usize * 2),
+        v if FIXARRAY.contains(v) => (1, 0, (v & !FIXARRAY_MASK) as usize),
+        v if FIXSTR.contains(v) => (1, (v & !FIXSTR_MASK) as usize, 0),
+        NIL | FALSE | TRUE => (1, 0, 0),
+        BIN8 | STR8 => (2, read_length(bytes, at, 1)?, 0),
+        BIN16 | STR16 => (1 + U16_BYTES, read_length(bytes, at, U16_BYTES)?, 0),
+        BIN32 | STR32 => (1 + U32_BYTES, read_length(bytes, at, U32_BYTES)?, 0),
+        EXT8 => (3, read_length(bytes, at, 1)?, 0),
+        EXT16 => (2 + U16_BYTES, read_length(bytes, at, U16_BYTES)?, 0),
+        EXT32 => (2 + U32_BYTES, read_length(bytes, at, U32_BYTES)?, 0),
+        UINT8 | INT8 => (2, 0, 0),
+        UINT16 | INT16 => (1 + U16_BYTES, 0, 0),
+        UINT32 | INT32 | FLOAT32 => (1 + U32_BYTES, 0, 0),
+        UINT64 | INT64 | FLOAT64 => (1 + U64_BYTES, 0, 0),
+        FIXEXT1 => (2, 1, 0),
+        FIXEXT2 => (2, 2, 0),
+        FIXEXT4 => (2, 4, 0),
+        FIXEXT8 => (2, 8, 0),
+        FIXEXT16 => (2, 16, 0),
+        ARRAY16 => (1 + U16_BYTES, 0, read_length(bytes, at, U16_BYTES)?),
+        ARRAY32 => (1 + U32_BYTES, 0, read_length(bytes, at, U32_BYTES)?),
+        MAP16 => (1 + U16_BYTES, 0, read_length(bytes, at, U16_BYTES)? * 2),
+        MAP32 => {
+            let count = read_length(bytes, at, U32_BYTES)?;
+            (1 + U32_BYTES, 0, count.checked_mul(2).ok_or(Error::TooBig)?)
+        }
+        _ => return Err(Error::BadType),
+    })
+}
+
+/// The number of bytes the value at the start of a buffer occupies, including everything nested
+/// inside it.
+pub(crate) fn value_len(bytes: &[u8]) -> Result<usize, Error> {
+    let mut position = 0;
+    let mut pending: usize = 1;
+
+    // walk values in order, keeping count of how many are still to come instead of recursing
+    while pending > 0 {
+        pending -= 1;
+
+        let (header_len, payload_len, children) = header(bytes, position)?;
+
+        position = (position + header_len).checked_add(payload_len).ok_or(Error::TooBig)?;
+        if position > bytes.len() {
+            return Err(Error::EndOfStream);
+        }
+
+        pending = pending.checked_add(children).ok_or(Error::TooBig)?;
+    }
+
+    Ok(position)
+}
+
+/// Check that the value at a position is well-formed, returning where it ends.
+fn validate_value(bytes: &[u8], start: usize) -> Result<usize, Error> {
+    // without a limit the whole value is always checked
+    validate_within(bytes, start, usize::MAX).map(|end| end.unwrap_or(bytes.len()))
+}
+
+/// Check that the value at a position is well-formed as far as `limit`, returning where it ends,
+/// or `None` if it runs on past `limit` without a problem in the bytes before it. Lengths that run
+/// past the end of the buffer are caught either way, since they only take reading a header.
+fn validate_within(bytes: &[u8], start: usize, limit: usize) -> Result<Option<usize>, Error> {
+    let mut position = start;
+    let mut pending: usize = 1;
+
+    while pending > 0 {
+        if position >= limit {
+            return Ok(None);
+        }
+
+        pending -= 1;
+
+        let (header_len, payload_len, children) = match header(bytes, position) {
+            Ok(header) => header,
+            Err(Error::BadType) => return Err(Error::Malformed(position, "invalid type byte")),
+            Err(Error::TooBig) => return Err(Error::Malformed(position, "length overflows")),
+            Err(_) => return Err(Error::Malformed(position, "truncated value")),
+        };
+
+        let payload = position + header_len;
+        let end = match payload.checked_add(payload_len) {
+            Some(end) if end <= bytes.len() => end,
+            _ => return Err(Error::Malformed(position, "truncated value")),
+        };
+
+        let ty = bytes[position];
+        let string = FIXSTR.contains(ty) || ty == STR8 || ty == STR16 || ty == STR32;
+        if string && payload < end {
+            // a string cut off by the limit may end partway through a character
+            let checked = end.min(limit.max(payload));
+            if let Err(error) = str::from_utf8(&bytes[payload..checked]) {
+                if checked == end || error.error_len().is_some() {
+                    return Err(Error::Malformed(position, "invalid UTF-8 in string"));
+                }
+            }
+        }
+
+        position = end;
+        pending = pending.checked_add(children)
+            .ok_or(Error::Malformed(position, "length overflows"))?;
+    }
+
+    Ok(Some(position))
+}
+
+/// Check that a buffer holds well-formed values, either exactly one or, for a stream, any number
+/// of them back to back.
+pub(crate) fn validate(bytes: &[u8], stream: bool) -> Result<(), Error> {
+    let mut position = 0;
+
+    if stream && bytes.is_empty() {
+        return Ok(());
+    }
+
+    loop {
+        position = validate_value(bytes, position)?;
+
+        if position == bytes.len() {
+            return Ok(());
+        } else if !stream {
+            return Err(Error::Malformed(position, "trailing data"));
+        }
+    }
+}
+
+/// Iterates over the byte ranges of the values in a buffer holding several values back to back.
+pub(crate) struct Values<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    failed: bool,
+    recover: bool,
+}
+
+impl<'a> Values<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Values<'a> {
+        Values {
+            bytes,
+            position: 0,
+            failed: false,
+            recover: false,
+        }
+    }
+
+    /// Iterate past malformed values instead of stopping at the first one.
+    pub(crate) fn recovering(bytes: &'a [u8]) -> Values<'a> {
+        Values { recover: true, ..Values::new(bytes) }
+    }
+
+    fn next_recovering(&mut self) -> Result<Range<usize>, Error> {
+        let start = self.position;
+
+        match validate_value(self.bytes, start) {
+            Ok(end) => {
+                self.position = end;
+                Ok(start..end)
+            }
+            Err(error) => {
+                self.position = resync(self.bytes, start + 1);
+                Err(error)
+            }
+        }
+    }
+}
+
+/// How many bytes from a candidate position `resync` checks before taking it as the start of a
+/// value.
+const RESYNC_LOOKAHEAD: usize = 64;
+
+/// Find the first position from `from` on that looks like the start of a value: one where the
+/// next `RESYNC_LOOKAHEAD` bytes hold a well-formed value, followed either by the end of the
+/// buffer or by the start of another well-formed value.
+///
+/// This is only a guess. The bytes of a damaged value can look well-formed themselves, so short
+/// bogus values may be found before the real next value, and a bogus container header can
+/// swallow the good values after it. Only the lookahead is checked at each candidate, so a run of
+/// garbage takes time linear in its length, and a value that only goes wrong past the lookahead
+/// is reported as malformed once it is read in full.
+fn resync(bytes: &[u8], from: usize) -> usize {
+    (from..bytes.len())
+        .find(|&position| {
+            let limit = position.saturating_add(RESYNC_LOOKAHEAD);
+
+            match validate_within(bytes, position, limit) {
+                Ok(Some(end)) => end == bytes.len() || validate_within(bytes, end, limit).is_ok(),
+                Ok(None) => true,
+                Err(_) => false,
+            }
+        })
+        .unwrap_or(bytes.len())
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = Result<Range<usize>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position == self.bytes.len() {
+            return None;
+        }
+
+        if self.recover {
+            return Some(self.next_recovering());
+        }
+
+        match value_len(&self.bytes[self.position..]) {
+            Ok(len) => {
+                let start = self.position;
+                self.position += len;
+
+                Some(Ok(start..self.position))
+            }
+            Err(error) => {
+                // there's no telling where the next value starts after a bad one
+                self.failed = true;
+
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Read the header of a map at the start of a buffer, returning the number of entries and the
+/// length of the header.
+pub(crate) fn map_header(bytes: &[u8]) -> Result<(usize, usize), Error> {
+    match bytes.first() {
+        Some(&v) if FIXMAP.contains(v) || v == MAP16 || v == MAP32 => {
+            let (header_len, _, children) = header(bytes, 0)?;
+            Ok((children / 2, header_len))
+        }
+        Some(_) => Err(Error::BadType),
+        None => Err(Error::EndOfStream),
+    }
+}
+
+/// Iterates over the entries of the map at the start of a buffer, yielding the byte ranges of
+/// each key and value.
+pub(crate) struct Entries<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    remaining: usize,
+}
+
+impl<'a> Entries<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Entries<'a>, Error> {
+        let (remaining, position) = map_header(bytes)?;
+
+        Ok(Entries {
+            bytes,
+            position,
+            remaining,
+        })
+    }
+
+    fn next_range(&mut self) -> Result<Range<usize>, Error> {
+        let start = self.position;
+        self.position += value_len(&self.bytes[start..])?;
+
+        Ok(start..self.position)
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<(Range<usize>, Range<usize>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let entry = self.next_range().and_then(|key| Ok((key, self.next_range()?)));
+
+        // a bad entry leaves nowhere to continue from
+        self.remaining = if entry.is_ok() { self.remaining - 1 } else { 0 };
+
+        Some(entry)
+    }
+}
+
+/// The number of bytes needed to hold an integer, or 0 if it fits in a fixint.
+fn integer_width(value: i128) -> usize {
+    if value >= FIXINT_MIN as i128 && value <= FIXINT_MAX as i128 {
+        0
+    } else if value >= i8::MIN as i128 && value <= u8::MAX as i128 {
+        1
+    } else if value >= i16::MIN as i128 && value <= u16::MAX as i128 {
+        U16_BYTES
+    } else if value >= i32::MIN as i128 && value <= u32::MAX as i128 {
+        U32_BYTES
+    } else {
+        U64_BYTES
+    }
+}
+
+/// Check that the value at a position uses its smallest encoding, returning what was wrong if not.
+fn check_minimal(bytes: &[u8],
+                 position: usize,
+                 payload_len: usize,
+                 children: usize)
+                 -> Option<&'static str> {
+    let data = &bytes[position + 1..];
+
+    let (width, value) = match bytes[position] {
+        UINT8 => (1, data[0] as i128),
+        UINT16 => (U16_BYTES, BigEndian::read_u16(data) as i128),
+        UINT32 => (U32_BYTES, BigEndian::read_u32(data) as i128),
+        UINT64 => (U64_BYTES, BigEndian::read_u64(data) as i128),
+        INT8 => (1, data[0] as i8 as i128),
+        INT16 => (U16_BYTES, BigEndian::read_i16(data) as i128),
+        INT32 => (U32_BYTES, BigEndian::read_i32(data) as i128),
+        INT64 => (U64_BYTES, BigEndian::read_i64(data) as i128),
+        FLOAT32 if !BigEndian::read_f32(data).is_finite() => return Some("non-finite float"),
+        FLOAT64 if !BigEndian::read_f64(data).is_finite() => return Some("non-finite float"),
+        STR8 if payload_len <= MAX_FIXSTR => return Some("string length is not minimal"),
+        STR16 if payload_len <= MAX_STR8 => return Some("string length is not minimal"),
+        STR32 if payload_len <= MAX_STR16 => return Some("string length is not minimal"),
+        BIN16 if payload_len <= MAX_BIN8 => return Some("binary length is not minimal"),
+        BIN32 if payload_len <= MAX_BIN16 => return Some("binary length is not minimal"),
+        ARRAY16 if children <= MAX_FIXARRAY => return Some("array length is not minimal"),
+        ARRAY32 if children <= MAX_ARRAY16 => return Some("array length is not minimal"),
+        MAP16 if children / 2 <= MAX_FIXMAP => return Some("map length is not minimal"),
+        MAP32 if children / 2 <= MAX_MAP16 => return Some("map length is not minimal"),
+        EXT8 if [1, 2, 4, 8, 16].contains(&payload_len) => {
+            return Some("extension length is not minimal")
+        }
+        EXT16 if payload_len <= u8::MAX as usize => return Some("extension length is not minimal"),
+        EXT32 if payload_len <= u16::MAX as usize => {
+            return Some("extension length is not minimal")
+        }
+        _ => return None,
+    };
+
+    if integer_width(value) < width {
+        Some("integer is not minimal")
+    } else {
+        None
+    }
+}
+
+/// A container being walked by verify_canonical.
+struct Frame {
+    total: usize,
+    remaining: usize,
+    map: bool,
+    key_start: usize,
+    last_key: Option<(usize, usize)>,
+}
+
+impl Frame {
+    fn next_is_key(&self) -> bool {
+        self.map && (self.total - self.remaining) % 2 == 0
+    }
+}
+
+/// Check that a buffer holds exactly one value in the canonical form written by
+/// `SerializerConfig::canonical`.
+pub(crate) fn verify_canonical(bytes: &[u8]) -> Result<(), Error> {
+    let mut position = 0;
+    let mut stack: Vec<Frame> = vec![];
+
+    loop {
+        let start = position;
+
+        if let Some(frame) = stack.last_mut() {
+            if frame.next_is_key() {
+                frame.key_start = start;
+            }
+        }
+
+        let (header_len, payload_len, children) = header(bytes, position)?;
+
+        position = (position + header_len).checked_add(payload_len).ok_or(Error::TooBig)?;
+        if position > bytes.len() {
+            return Err(Error::EndOfStream);
+        }
+
+        if let Some(reason) = check_minimal(bytes, start, payload_len, children) {
+            return Err(Error::NonCanonical(start, reason));
+        }
+
+        if children > 0 {
+            let ty = bytes[start];
+
+            stack.push(Frame {
+                           total: children,
+                           remaining: children,
+                           map: FIXMAP.contains(ty) || ty == MAP16 || ty == MAP32,
+                           key_start: 0,
+                           last_key: None,
+                       });
+            continue;
+        }
+
+        // the value is complete, along with any containers it was the last element of
+        loop {
+            let finished = match stack.last_mut() {
+                None => {
+                    if position != bytes.len() {
+                        return Err(Error::NonCanonical(position, "trailing data"));
+                    }
+
+                    return Ok(());
+                }
+                Some(frame) => {
+                    if frame.next_is_key() {
+                        let key = (frame.key_start, position);
+
+                        if let Some(last) = frame.last_key {
+                            match bytes[last.0..last.1].cmp(&bytes[key.0..key.1]) {
+                                Ordering::Less => {}
+                                Ordering::Equal => {
+                                    return Err(Error::NonCanonical(key.0, "duplicate map key"));
+                                }
+                                Ordering::Greater => {
+                                    return Err(Error::NonCanonical(key.0,
+                                                                   "map keys are not sorted"));
+                                }
+                            }
+                        }
+
+                        frame.last_key = Some(key);
+                    }
+
+                    frame.remaining -= 1;
+                    frame.remaining == 0
+                }
+            };
+
+            if !finished {
+                break;
+            }
+
+            stack.pop();
+        }
+    }
+}
@@ -3,24 +3,81 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License,
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::{Vec, String};
+
+#[cfg(feature = "alloc")]
+use alloc::string::ToString;
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use alloc::BTreeSet;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+
 use serde::de::{SeqAccess, MapAccess, DeserializeSeed};
 
 use de::Deserializer;
 
+use byteorder::{ByteOrder, BigEndian};
+
+use defs::*;
+
 use error::Error;
 use read::Read;
 
 pub struct SeqDeserializer<'de: 'a, 'a, R: 'a + Read<'de>> {
     de: &'a mut Deserializer<'de, R>,
     count: usize,
+    index: usize,
+    keys: BTreeSet<Vec<u8>>,
+    segment: Option<String>,
 }
 
 impl<'de, 'a, R: Read<'de>> SeqDeserializer<'de, 'a, R> {
     pub fn new(de: &'a mut Deserializer<'de, R>, count: usize) -> SeqDeserializer<'de, 'a, R> {
         SeqDeserializer {
-            de: de,
-            count: count,
+            de,
+            count,
+            index: 0,
+            keys: BTreeSet::new(),
+            segment: None,
+        }
+    }
+
+    fn tracking_keys(&self) -> bool {
+        self.de.config().deny_duplicate_keys
+    }
+
+    fn visit_key<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+        where T: DeserializeSeed<'de>
+    {
+        if self.count == 0 {
+            return Ok(None);
+        }
+
+        let start = self.de.start_capture();
+        let key = self.visit_item(seed);
+        let bytes = self.de.end_capture(start);
+        let key = key?;
+
+        self.segment = Some(describe_key(&bytes));
+
+        if !self.keys.insert(normalize_key(bytes)?) {
+            return Err(Error::DuplicateKey(self.de.path()));
         }
+
+        Ok(key)
+    }
+
+    fn visit_at<T>(&mut self, segment: String, seed: T) -> Result<Option<T::Value>, Error>
+        where T: DeserializeSeed<'de>
+    {
+        self.de.push_path(segment);
+        let result = self.visit_item(seed);
+        self.de.pop_path();
+
+        result
     }
 
     fn visit_item<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
@@ -32,7 +89,7 @@ impl<'de, 'a, R: Read<'de>> SeqDeserializer<'de, 'a, R> {
 
         self.count -= 1;
 
-        Ok(Some(try!(seed.deserialize(&mut *self.de))))
+        Ok(Some(seed.deserialize(&mut *self.de)?))
     }
 }
 
@@ -42,7 +99,13 @@ impl<'de, 'a, R: Read<'de>> SeqAccess<'de> for SeqDeserializer<'de, 'a, R> {
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
         where T: DeserializeSeed<'de>
     {
-        self.visit_item(seed)
+        if self.tracking_keys() {
+            let index = self.index;
+            self.index += 1;
+            self.visit_at(index.to_string(), seed)
+        } else {
+            self.visit_item(seed)
+        }
     }
 
     fn size_hint(&self) -> Option<usize> {
@@ -56,17 +119,108 @@ impl<'de, 'a, R: Read<'de>> MapAccess<'de> for SeqDeserializer<'de, 'a, R> {
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
         where K: DeserializeSeed<'de>
     {
-        self.visit_item(seed)
+        if self.tracking_keys() {
+            self.visit_key(seed)
+        } else {
+            self.visit_item(seed)
+        }
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
         where V: DeserializeSeed<'de>
     {
-        self.visit_item(seed)
-            .and_then(|maybe_value| maybe_value.ok_or(Error::EndOfStream))
+        let value = match self.segment.take() {
+            Some(segment) => self.visit_at(segment, seed),
+            None => self.visit_item(seed),
+        };
+
+        value.and_then(|maybe_value| maybe_value.ok_or(Error::EndOfStream))
     }
 
     fn size_hint(&self) -> Option<usize> {
         Some((self.count + 1) / 2)
     }
 }
+
+/// The value of a numeric map key.
+enum Number {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+/// Bring a numeric key to a single fixed-width encoding, so keys written with different integer
+/// widths or signedness compare equal when they hold the same number: uint64 for non-negative
+/// integers, int64 for negative ones and float64 for floats. Float keys have -0.0 taken as 0.0,
+/// and a NaN key, which could never equal another, fails with `Error::InvalidKey`. Other keys are
+/// compared as is.
+///
+/// The key has already been decoded, so its bytes are known to be complete.
+fn normalize_key(mut bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let number = {
+        let (ty, payload) = match bytes.split_first() {
+            Some((&ty, payload)) => (ty, payload),
+            None => return Ok(bytes),
+        };
+
+        match ty {
+            v if POS_FIXINT.contains(v) => Number::Unsigned(v as u64),
+            v if NEG_FIXINT.contains(v) => Number::Signed(v as i8 as i64),
+            UINT8 => Number::Unsigned(payload[0] as u64),
+            UINT16 => Number::Unsigned(BigEndian::read_u16(payload) as u64),
+            UINT32 => Number::Unsigned(BigEndian::read_u32(payload) as u64),
+            UINT64 => Number::Unsigned(BigEndian::read_u64(payload)),
+            INT8 => Number::Signed(payload[0] as i8 as i64),
+            INT16 => Number::Signed(BigEndian::read_i16(payload) as i64),
+            INT32 => Number::Signed(BigEndian::read_i32(payload) as i64),
+            INT64 => Number::Signed(BigEndian::read_i64(payload)),
+            FLOAT32 => Number::Float(BigEndian::read_f32(payload) as f64),
+            FLOAT64 => Number::Float(BigEndian::read_f64(payload)),
+            _ => return Ok(bytes),
+        }
+    };
+
+    let mut buf = [0; U64_BYTES + 1];
+
+    match number {
+        Number::Signed(value) if value < 0 => {
+            buf[0] = INT64;
+            BigEndian::write_i64(&mut buf[1..], value);
+        }
+        Number::Signed(value) => {
+            buf[0] = UINT64;
+            BigEndian::write_u64(&mut buf[1..], value as u64);
+        }
+        Number::Unsigned(value) => {
+            buf[0] = UINT64;
+            BigEndian::write_u64(&mut buf[1..], value);
+        }
+        Number::Float(value) => {
+            if value.is_nan() {
+                return Err(Error::InvalidKey);
+            }
+
+            // adding zero turns -0.0 into 0.0 and leaves every other number alone
+            buf[0] = FLOAT64;
+            BigEndian::write_f64(&mut buf[1..], value + 0.0);
+        }
+    }
+
+    bytes.clear();
+    bytes.extend_from_slice(&buf);
+
+    Ok(bytes)
+}
+
+/// Render a raw map key for use in an error path.
+fn describe_key(bytes: &[u8]) -> String {
+    if let Ok(key) = ::from_bytes::<&str>(bytes) {
+        key.into()
+    } else if let Ok(key) = ::from_bytes::<i64>(bytes) {
+        key.to_string()
+    } else if let Ok(key) = ::from_bytes::<u64>(bytes) {
+        key.to_string()
+    } else {
+        "?".into()
+    }
+}
@@ -13,24 +13,37 @@ use byteorder::{ByteOrder, BigEndian};
 
 use ser::Serializer;
 
+use config::SerializerConfig;
+use intern::StringTable;
+
 use error::Error;
+use write::{Write, Forward};
 
 use defs::*;
 
-pub struct SeqSerializer<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> {
+pub struct SeqSerializer<'a, F: 'a + Write> {
     count: usize,
     size: Option<usize>,
     buffer: Vec<u8>,
+    patch_position: Option<usize>,
     output: &'a mut F,
+    config: SerializerConfig,
+    strings: &'a mut StringTable,
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SeqSerializer<'a, F> {
-    pub fn new(output: &'a mut F) -> SeqSerializer<'a, F> {
+impl<'a, F: 'a + Write> SeqSerializer<'a, F> {
+    pub(crate) fn new(output: &'a mut F,
+                      config: SerializerConfig,
+                      strings: &'a mut StringTable)
+                      -> SeqSerializer<'a, F> {
         SeqSerializer {
             count: 0,
             size: None,
             buffer: vec![],
-            output: output,
+            patch_position: None,
+            output,
+            config,
+            strings,
         }
     }
 
@@ -40,6 +53,11 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SeqSerializer<'a, F> {
         if let Some(size) = self.size {
             // output this now because we know it
             self.output_sequence_header(size)
+        } else if let (true, Some(position)) = (self.config.backpatch_sequences,
+                                                self.output.position()) {
+            // write a placeholder header now and fill in the length at the end
+            self.patch_position = Some(position);
+            self.output.write(&[ARRAY32, 0, 0, 0, 0])
         } else {
             Ok(())
         }
@@ -61,10 +79,18 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SeqSerializer<'a, F> {
         if let Some(size) = self.size {
             self.check_item_count_matches_size(size)?;
             Ok(())
+        } else if let Some(position) = self.patch_position {
+            if self.count > MAX_ARRAY32 {
+                return Err(Error::TooBig);
+            }
+
+            let mut buf = [0; U32_BYTES];
+            BigEndian::write_u32(&mut buf, self.count as u32);
+            self.output.patch(position + 1, &buf)
         } else {
             let count = self.count;
             self.output_sequence_header(count)?;
-            (self.output)(self.buffer.as_slice())
+            self.output.write(self.buffer.as_slice())
         }
     }
 
@@ -77,46 +103,47 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SeqSerializer<'a, F> {
     }
 
     fn should_serialize_directly(&mut self) -> bool {
-        self.size.is_some()
+        self.size.is_some() || self.patch_position.is_some()
     }
 
     fn serialize_into_buffer<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        let mut target = Serializer::new(|bytes| {
-            self.buffer.extend_from_slice(bytes);
-            Ok(())
-        });
+        let buffer = &mut self.buffer;
 
-        value.serialize(&mut target)
+        Serializer::serialize_nested(|bytes: &[u8]| {
+                                         buffer.extend_from_slice(bytes);
+                                         Ok(())
+                                     },
+                                     self.config,
+                                     self.strings,
+                                     value)
     }
 
     fn serialize_directly<T>(&mut self, value: &T) -> Result<(), Error>
         where T: ?Sized + Serialize
     {
-        let mut target = Serializer::new(|bytes| (self.output)(bytes));
-
-        value.serialize(&mut target)
+        Serializer::serialize_nested(Forward(&mut *self.output), self.config, self.strings, value)
     }
 
     fn output_sequence_header(&mut self, size: usize) -> Result<(), Error> {
         if size <= MAX_FIXARRAY {
-            (self.output)(&[size as u8 | FIXARRAY_MASK])
+            self.output.write(&[size as u8 | FIXARRAY_MASK])
         } else if size <= MAX_ARRAY16 {
             let mut buf = [ARRAY16; U16_BYTES + 1];
             BigEndian::write_u16(&mut buf[1..], size as u16);
-            (self.output)(&buf)
+            self.output.write(&buf)
         } else if size <= MAX_ARRAY32 {
             let mut buf = [ARRAY32; U32_BYTES + 1];
             BigEndian::write_u32(&mut buf[1..], size as u32);
-            (self.output)(&buf)
+            self.output.write(&buf)
         } else {
             Err(Error::TooBig)
         }
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeSeq for SeqSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeSeq for SeqSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
@@ -131,7 +158,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeSeq for SeqSerializ
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeTupleVariant for SeqSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeTupleVariant for SeqSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
@@ -146,7 +173,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeTupleVariant for Se
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeTupleStruct for SeqSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeTupleStruct for SeqSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
@@ -161,7 +188,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeTupleStruct for Seq
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> SerializeTuple for SeqSerializer<'a, F> {
+impl<'a, F: 'a + Write> SerializeTuple for SeqSerializer<'a, F> {
     type Ok = ();
     type Error = Error;
 
@@ -3,147 +3,295 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License,
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
+use std::mem;
 use std::result;
 
-use byteorder::{ByteOrder, BigEndian, LittleEndian};
+use byteorder::{ByteOrder, BigEndian};
 
 use serde::Serialize;
 
 use serde;
 
 use error::Error;
+use write::{BufferedWrite, Write};
 
+use config::SerializerConfig;
 use defs::*;
+use intern::{self, StringTable};
 use seq_serializer::*;
 use map_serializer::*;
 
-/// The corepack Serializer. Contains a closure that receives byte buffers as the output is created.
-pub struct Serializer<F: FnMut(&[u8]) -> Result<(), Error>> {
+/// The corepack Serializer. Contains an output that receives byte buffers as they are created.
+pub struct Serializer<F: Write> {
     output: F,
+    config: SerializerConfig,
+    strings: StringTable,
 }
 
 impl<F: FnMut(&[u8]) -> Result<(), Error>> Serializer<F> {
-    /// Create a new Deserializer given an input function.
+    /// Create a new Serializer given an output function.
     pub fn new(output: F) -> Serializer<F> {
-        Serializer { output: output }
+        Serializer::with_config(output, SerializerConfig::default())
+    }
+
+    /// Create a new Serializer given an output function and a configuration.
+    pub fn with_config(output: F, config: SerializerConfig) -> Serializer<F> {
+        Serializer::from_write_with_config(output, config)
+    }
+
+    /// Create a new Serializer that produces canonical output. See `SerializerConfig::canonical`
+    /// for the rules.
+    pub fn canonical(output: F) -> Serializer<F> {
+        Serializer::with_config(output, SerializerConfig::canonical())
+    }
+}
+
+impl<W: Write> Serializer<BufferedWrite<W>> {
+    /// Create a new Serializer that passes its output on to another output in chunks of at least
+    /// `chunk_size` bytes, instead of in the many small pieces values are written in. Call `flush`
+    /// once done serializing to write out the rest.
+    pub fn buffered(output: W, chunk_size: usize) -> Serializer<BufferedWrite<W>> {
+        Serializer::from_write(BufferedWrite::new(output, chunk_size))
+    }
+
+    /// Write out any output that is still buffered.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+}
+
+impl<F: Write> Serializer<F> {
+    /// Create a new Serializer given an output.
+    pub fn from_write(output: F) -> Serializer<F> {
+        Serializer::from_write_with_config(output, SerializerConfig::default())
+    }
+
+    /// Create a new Serializer given an output and a configuration.
+    pub fn from_write_with_config(output: F, config: SerializerConfig) -> Serializer<F> {
+        Serializer {
+            output,
+            config,
+            strings: StringTable::default(),
+        }
+    }
+
+    /// Unwrap the output.
+    pub fn into_inner(self) -> F {
+        self.output
+    }
+
+    /// Write the header of an extension value with a payload of `len` bytes, leaving the payload
+    /// itself to be written with `write_raw`. This lets a large payload be streamed to the
+    /// output in pieces instead of being held in memory.
+    ///
+    /// The caller must write exactly `len` bytes of payload before serializing anything else.
+    pub fn write_ext_header(&mut self, tag: i8, len: usize) -> Result<(), Error> {
+        match len {
+            1 => self.output.write(&[FIXEXT1, tag as u8]),
+            2 => self.output.write(&[FIXEXT2, tag as u8]),
+            4 => self.output.write(&[FIXEXT4, tag as u8]),
+            8 => self.output.write(&[FIXEXT8, tag as u8]),
+            16 => self.output.write(&[FIXEXT16, tag as u8]),
+            len if len <= MAX_EXT8 => self.output.write(&[EXT8, len as u8, tag as u8]),
+            len if len <= MAX_EXT16 => {
+                let mut buf = [EXT16; U16_BYTES + 2];
+                BigEndian::write_u16(&mut buf[1..], len as u16);
+                buf[U16_BYTES + 1] = tag as u8;
+                self.output.write(&buf)
+            }
+            len if len <= MAX_EXT32 => {
+                let mut buf = [EXT32; U32_BYTES + 2];
+                BigEndian::write_u32(&mut buf[1..], len as u32);
+                buf[U32_BYTES + 1] = tag as u8;
+                self.output.write(&buf)
+            }
+            _ => Err(Error::TooBig),
+        }
+    }
+
+    /// Write bytes straight to the output, such as the payload after `write_ext_header`. Nothing
+    /// checks that they form valid MessagePack.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.output.write(bytes)
+    }
+
+    /// Serialize a value into another output, sharing the given string table.
+    pub(crate) fn serialize_nested<T>(output: F,
+                                      config: SerializerConfig,
+                                      strings: &mut StringTable,
+                                      value: &T)
+                                      -> Result<(), Error>
+        where T: ?Sized + Serialize
+    {
+        let mut target = Serializer {
+            output,
+            config,
+            strings: mem::take(strings),
+        };
+
+        let result = value.serialize(&mut target);
+        *strings = target.strings;
+
+        result
+    }
+
+    fn degrade(&mut self, error: Error) -> Result<(), Error> {
+        // in lossy mode, values we can't represent become nil instead of failing
+        if let Some(observer) = self.config.lossy {
+            observer(&error);
+            self.serialize_unit()
+        } else {
+            Err(error)
+        }
     }
 
     fn serialize_signed(&mut self, value: i64) -> Result<(), Error> {
-        if value >= FIXINT_MIN as i64 && value <= FIXINT_MAX as i64 {
-            let mut buf = [0; U16_BYTES];
-            LittleEndian::write_i16(&mut buf, value as i16);
-            (self.output)(&buf[..1])
-        } else if value >= i8::min_value() as i64 && value <= i8::max_value() as i64 {
-            let mut buf = [0; U16_BYTES];
-            LittleEndian::write_i16(&mut buf, value as i16);
-            (self.output)(&[INT8, buf[0]])
-        } else if value >= 0 && value <= u8::max_value() as i64 {
-            let mut buf = [0; U16_BYTES];
-            LittleEndian::write_i16(&mut buf, value as i16);
-            (self.output)(&[UINT8, buf[0]])
-        } else if value >= i16::min_value() as i64 && value <= i16::max_value() as i64 {
+        // non-negative values use the unsigned forms, which every decoder accepts for any type
+        if value >= 0 {
+            return self.serialize_unsigned(value as u64);
+        }
+
+        if value >= FIXINT_MIN as i64 {
+            self.output.write(&[value as u8])
+        } else if value >= i8::MIN as i64 {
+            self.output.write(&[INT8, value as u8])
+        } else if value >= i16::MIN as i64 {
             let mut buf = [INT16; U16_BYTES + 1];
             BigEndian::write_i16(&mut buf[1..], value as i16);
-            (self.output)(&buf)
-        } else if value >= 0 && value <= u16::max_value() as i64 {
-            let mut buf = [UINT16; U16_BYTES + 1];
-            BigEndian::write_u16(&mut buf[1..], value as u16);
-            (self.output)(&buf)
-        } else if value >= i32::min_value() as i64 && value <= i32::max_value() as i64 {
+            self.output.write(&buf)
+        } else if value >= i32::MIN as i64 {
             let mut buf = [INT32; U32_BYTES + 1];
             BigEndian::write_i32(&mut buf[1..], value as i32);
-            (self.output)(&buf)
-        } else if value >= 0 && value <= u32::max_value() as i64 {
-            let mut buf = [UINT32; U16_BYTES + 1];
-            BigEndian::write_u32(&mut buf[1..], value as u32);
-            (self.output)(&buf)
+            self.output.write(&buf)
         } else {
             let mut buf = [INT64; U64_BYTES + 1];
             BigEndian::write_i64(&mut buf[1..], value);
-            (self.output)(&buf)
+            self.output.write(&buf)
         }
     }
 
     fn serialize_unsigned(&mut self, value: u64) -> Result<(), Error> {
         if value <= FIXINT_MAX as u64 {
-            (self.output)(&[value as u8])
-        } else if value <= u8::max_value() as u64 {
-            (self.output)(&[UINT8, value as u8])
-        } else if value <= u16::max_value() as u64 {
+            self.output.write(&[value as u8])
+        } else if value <= u8::MAX as u64 {
+            self.output.write(&[UINT8, value as u8])
+        } else if value <= u16::MAX as u64 {
             let mut buf = [UINT16; U16_BYTES + 1];
             BigEndian::write_u16(&mut buf[1..], value as u16);
-            (self.output)(&buf)
-        } else if value <= u32::max_value() as u64 {
+            self.output.write(&buf)
+        } else if value <= u32::MAX as u64 {
             let mut buf = [UINT32; U32_BYTES + 1];
             BigEndian::write_u32(&mut buf[1..], value as u32);
-            (self.output)(&buf)
+            self.output.write(&buf)
         } else {
             let mut buf = [UINT64; U64_BYTES + 1];
             BigEndian::write_u64(&mut buf[1..], value);
-            (self.output)(&buf)
+            self.output.write(&buf)
         }
     }
 
     fn serialize_bool(&mut self, value: bool) -> Result<(), Error> {
         if value {
-            (self.output)(&[TRUE])
+            self.output.write(&[TRUE])
         } else {
-            (self.output)(&[FALSE])
+            self.output.write(&[FALSE])
         }
     }
 
     fn serialize_f32(&mut self, value: f32) -> Result<(), Error> {
+        if self.config.reject_non_finite && !value.is_finite() {
+            return self.degrade(Error::NonFinite);
+        }
+
         let mut buf = [FLOAT32; U32_BYTES + 1];
         BigEndian::write_f32(&mut buf[1..], value);
-        (self.output)(&buf)
+        self.output.write(&buf)
     }
 
     fn serialize_f64(&mut self, value: f64) -> Result<(), Error> {
+        if self.config.reject_non_finite && !value.is_finite() {
+            return self.degrade(Error::NonFinite);
+        }
+
         let mut buf = [FLOAT64; U64_BYTES + 1];
         BigEndian::write_f64(&mut buf[1..], value);
-        (self.output)(&buf)
+        self.output.write(&buf)
     }
 
     fn serialize_bytes(&mut self, value: &[u8]) -> Result<(), Error> {
+        if self.config.legacy_raw {
+            return self.serialize_legacy_raw(value);
+        }
+
         if value.len() <= MAX_BIN8 {
-            try!((self.output)(&[BIN8, value.len() as u8]));
+            self.output.write(&[BIN8, value.len() as u8])?;
         } else if value.len() <= MAX_BIN16 {
             let mut buf = [BIN16; U16_BYTES + 1];
             BigEndian::write_u16(&mut buf[1..], value.len() as u16);
-            try!((self.output)(&buf));
+            self.output.write(&buf)?;
         } else if value.len() <= MAX_BIN32 {
             let mut buf = [BIN32; U32_BYTES + 1];
             BigEndian::write_u32(&mut buf[1..], value.len() as u32);
-            try!((self.output)(&buf));
+            self.output.write(&buf)?;
         } else {
-            return Err(Error::TooBig);
+            return self.degrade(Error::TooBig);
         }
 
-        (self.output)(value)
+        self.output.write(value)
     }
 
     fn serialize_str(&mut self, value: &str) -> Result<(), Error> {
+        if self.config.intern_strings {
+            if let Some(index) = self.strings.intern(value) {
+                let mut buf = [0; U32_BYTES + 2];
+                return self.output.write(intern::encode_ref(index, &mut buf));
+            }
+        }
+
+        if self.config.legacy_raw {
+            return self.serialize_legacy_raw(value.as_bytes());
+        }
+
         if value.len() <= MAX_FIXSTR {
-            try!((self.output)(&[value.len() as u8 | FIXSTR_MASK]));
+            self.output.write(&[value.len() as u8 | FIXSTR_MASK])?;
         } else if value.len() <= MAX_STR8 {
-            try!((self.output)(&[STR8, value.len() as u8]));
+            self.output.write(&[STR8, value.len() as u8])?;
         } else if value.len() <= MAX_STR16 {
             let mut buf = [STR16; U16_BYTES + 1];
             BigEndian::write_u16(&mut buf[1..], value.len() as u16);
-            try!((self.output)(&buf));
+            self.output.write(&buf)?;
         } else if value.len() <= MAX_STR32 {
             let mut buf = [STR32; U32_BYTES + 1];
             BigEndian::write_u32(&mut buf[1..], value.len() as u32);
-            try!((self.output)(&buf));
+            self.output.write(&buf)?;
         } else {
-            return Err(Error::TooBig);
+            return self.degrade(Error::TooBig);
         }
 
-        (self.output)(value.as_bytes())
+        self.output.write(value.as_bytes())
+    }
+
+    fn serialize_legacy_raw(&mut self, value: &[u8]) -> Result<(), Error> {
+        // the old spec had a single raw family sharing the fixstr, str16 and str32 type bytes
+        if value.len() <= MAX_FIXSTR {
+            self.output.write(&[value.len() as u8 | FIXSTR_MASK])?;
+        } else if value.len() <= MAX_STR16 {
+            let mut buf = [STR16; U16_BYTES + 1];
+            BigEndian::write_u16(&mut buf[1..], value.len() as u16);
+            self.output.write(&buf)?;
+        } else if value.len() <= MAX_STR32 {
+            let mut buf = [STR32; U32_BYTES + 1];
+            BigEndian::write_u32(&mut buf[1..], value.len() as u32);
+            self.output.write(&buf)?;
+        } else {
+            return self.degrade(Error::TooBig);
+        }
+
+        self.output.write(value)
     }
 
     fn serialize_unit(&mut self) -> Result<(), Error> {
-        (self.output)(&[NIL])
+        self.output.write(&[NIL])
     }
 
     fn serialize_variant(&mut self, variant_index: u32) -> Result<(), Error> {
@@ -152,14 +300,14 @@ impl<F: FnMut(&[u8]) -> Result<(), Error>> Serializer<F> {
         // the variant once we get it going.
 
         // start a two element array
-        (self.output)(&[2u8 | FIXARRAY_MASK])?;
+        self.output.write(&[2u8 | FIXARRAY_MASK])?;
 
         // encode the variant and done
         self.serialize_unsigned(variant_index as u64)
     }
 }
 
-impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mut Serializer<F> {
+impl<'a, F: 'a + Write> serde::Serializer for &'a mut Serializer<F> {
     type Ok = ();
     type Error = Error;
 
@@ -173,7 +321,7 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
     type SerializeStructVariant = Self::SerializeMap;
 
     fn serialize_seq(self, size: Option<usize>) -> result::Result<Self::SerializeSeq, Self::Error> {
-        let mut seq = SeqSerializer::new(&mut self.output);
+        let mut seq = SeqSerializer::new(&mut self.output, self.config, &mut self.strings);
 
         seq.hint_size(size)?;
 
@@ -181,8 +329,9 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
     }
 
     fn serialize_map(self, size: Option<usize>) -> result::Result<Self::SerializeMap, Self::Error> {
-        let mut map = MapSerializer::new(&mut self.output);
+        let mut map = MapSerializer::new(&mut self.output, self.config, &mut self.strings);
 
+        map.presorted();
         map.hint_size(size)?;
 
         Ok(map)
@@ -200,6 +349,14 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
         Serializer::serialize_unsigned(self, value)
     }
 
+    fn serialize_i128(self, _: i128) -> Result<(), Error> {
+        self.degrade(Error::TooBig)
+    }
+
+    fn serialize_u128(self, _: u128) -> Result<(), Error> {
+        self.degrade(Error::TooBig)
+    }
+
     fn serialize_f32(self, value: f32) -> Result<(), Error> {
         Serializer::serialize_f32(self, value)
     }
@@ -245,19 +402,35 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
     }
 
     fn serialize_char(self, v: char) -> Result<(), Error> {
+        if self.config.char_as_uint {
+            return Serializer::serialize_unsigned(self, v as u64);
+        }
+
         let mut buf = [0; 4];
         self.serialize_str(v.encode_utf8(&mut buf))
     }
 
     fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> {
-        self.serialize_unit()
+        if self.config.unit_struct_as_empty_map {
+            self.output.write(&[FIXMAP_MASK])
+        } else {
+            self.serialize_unit()
+        }
     }
 
     fn serialize_unit_variant(self,
                               _: &'static str,
                               index: u32,
-                              _: &'static str)
+                              variant: &'static str)
                               -> Result<(), Error> {
+        if self.config.unit_variant_as_str {
+            return serde::Serializer::serialize_str(self, variant);
+        }
+
+        if self.config.unit_variant_as_uint {
+            return Serializer::serialize_unsigned(self, index as u64);
+        }
+
         self.serialize_variant(index)?;
         self.serialize_unit()
     }
@@ -316,7 +489,18 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
                         _: &'static str,
                         len: usize)
                         -> result::Result<Self::SerializeStruct, Self::Error> {
-        self.serialize_map(Some(len))
+        let mut map = MapSerializer::new(&mut self.output, self.config, &mut self.strings);
+
+        if self.config.struct_as_array {
+            map.positional();
+            map.hint_size(Some(len))?;
+        } else if self.config.skip_none_fields {
+            map.skip_none_fields()?;
+        } else {
+            map.hint_size(Some(len))?;
+        }
+
+        Ok(map)
     }
 
     fn serialize_struct_variant(self,
@@ -333,6 +517,17 @@ impl<'a, F: 'a + FnMut(&[u8]) -> Result<(), Error>> serde::Serializer for &'a mu
 #[cfg(test)]
 mod test {
     use std::collections::BTreeMap;
+    use std::f64;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use std::io::{Cursor, Write};
+
+    use serde::{Serialize, Serializer};
+    use serde::ser::SerializeSeq;
+
+    use config::SerializerConfig;
+    use error::Error;
+    use write::SeekWrite;
 
     #[test]
     fn positive_fixint_test() {
@@ -375,13 +570,27 @@ mod test {
                    &[0x94, 0x05, 0x08, 0x14, 0xcc, 0xe7]);
     }
 
+    #[test]
+    fn signed_as_unsigned_test() {
+        assert_eq!(::to_bytes(5i64).unwrap(), &[0x05]);
+        assert_eq!(::to_bytes(200i16).unwrap(), &[0xcc, 0xc8]);
+        assert_eq!(::to_bytes(40000i32).unwrap(), &[0xcd, 0x9c, 0x40]);
+        assert_eq!(::to_bytes(i64::MAX).unwrap(),
+                   &[0xcf, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        // only negative values use the signed forms
+        assert_eq!(::to_bytes(-1i64).unwrap(), &[0xff]);
+        assert_eq!(::to_bytes(-100i8).unwrap(), &[0xd0, 0x9c]);
+        assert_eq!(::to_bytes(-200i64).unwrap(), &[0xd1, 0xff, 0x38]);
+    }
+
     #[test]
     fn array16_test() {
         let v: Vec<isize> = vec![-5, 16, 101, -45, 184, 89, 62, -233, -33, 304, 76, 90, 23, 108,
                                  45, -3, 2];
         assert_eq!(::to_bytes(v).unwrap(),
                    &[0xdc, 0x00, 0x11, 0xfb, 0x10, 0x65, 0xd0, 0xd3, 0xcc, 0xb8, 0x59, 0x3e,
-                     0xd1, 0xff, 0x17, 0xd0, 0xdf, 0xd1, 0x01, 0x30, 0x4c, 0x5a, 0x17, 0x6c,
+                     0xd1, 0xff, 0x17, 0xd0, 0xdf, 0xcd, 0x01, 0x30, 0x4c, 0x5a, 0x17, 0x6c,
                      0x2d, 0xfd, 0x02]);
     }
 
@@ -395,4 +604,521 @@ mod test {
                    &[0x83, 0xa3, 0x6f, 0x6e, 0x65, 0x01, 0xa5, 0x74, 0x68, 0x72, 0x65, 0x65,
                      0x03, 0xa3, 0x74, 0x77, 0x6f, 0x02]);
     }
+
+    static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_warning(_: &Error) {
+        WARNINGS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn lossy_non_finite_test() {
+        let config = SerializerConfig::new().reject_non_finite(true);
+
+        match ::to_bytes_with_config(vec![1.0, f64::NAN], config) {
+            Err(Error::NonFinite) => {}
+            other => panic!("expected NonFinite, got {:?}", other),
+        }
+
+        let config = config.lossy(count_warning);
+        assert_eq!(::to_bytes_with_config(vec![1.0, f64::NAN], config).unwrap(),
+                   &[0x92, 0xcb, 0x3f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0]);
+        assert_eq!(WARNINGS.load(Ordering::SeqCst), 1);
+    }
+
+    struct UnknownLength(Vec<u32>);
+
+    impl Serialize for UnknownLength {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(None)?;
+            for value in &self.0 {
+                seq.serialize_element(value)?;
+            }
+            seq.end()
+        }
+    }
+
+    #[test]
+    fn backpatch_sequence_test() {
+        let value = UnknownLength((0..20).collect());
+        let config = SerializerConfig::new().backpatch_sequences(true);
+
+        let mut cursor = Cursor::new(vec![]);
+        cursor.write_all(&[0xc0]).unwrap();
+
+        {
+            let output = SeekWrite::new(&mut cursor).unwrap();
+            let mut ser = ::Serializer::from_write_with_config(output, config);
+            value.serialize(&mut ser).unwrap();
+        }
+
+        let bytes = cursor.into_inner();
+        assert_eq!(&bytes[..6], &[0xc0, 0xdd, 0x00, 0x00, 0x00, 0x14]);
+        assert_eq!(bytes.len(), 26);
+
+        let decoded: Vec<u32> = ::from_bytes(&bytes[1..]).unwrap();
+        assert_eq!(decoded, value.0);
+
+        // append-only outputs fall back to buffering for the minimal header
+        let mut bytes = vec![];
+        {
+            let mut ser = ::Serializer::with_config(|buf: &[u8]| {
+                                                        bytes.extend_from_slice(buf);
+                                                        Ok(())
+                                                    },
+                                                    config);
+            value.serialize(&mut ser).unwrap();
+        }
+        assert_eq!(&bytes[..3], &[0xdc, 0x00, 0x14]);
+    }
+
+    #[test]
+    fn slice_streams_directly_test() {
+        let values: Vec<u32> = (0..1000).collect();
+        let slice: &[u32] = &values;
+
+        let mut writes: Vec<Vec<u8>> = vec![];
+        {
+            let mut ser = ::Serializer::new(|buf: &[u8]| {
+                writes.push(buf.to_vec());
+                Ok(())
+            });
+            slice.serialize(&mut ser).unwrap();
+        }
+
+        // the header goes out first, then one write per element with nothing buffered
+        assert_eq!(writes[0], &[0xdc, 0x03, 0xe8]);
+        assert_eq!(writes.len(), 1001);
+        assert_eq!(writes[1], &[0x00]);
+        assert_eq!(writes[1000], &[0xcd, 0x03, 0xe7]);
+    }
+
+    #[test]
+    fn big_endian_ints_test() {
+        assert_eq!(::to_bytes(-300i16).unwrap(), &[0xd1, 0xfe, 0xd4]);
+        assert_eq!(::to_bytes(0x1234u16).unwrap(), &[0xcd, 0x12, 0x34]);
+        assert_eq!(::to_bytes(-0x1234567i32).unwrap(), &[0xd2, 0xfe, 0xdc, 0xba, 0x99]);
+        assert_eq!(::to_bytes(0x12345678u32).unwrap(), &[0xce, 0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(::to_bytes(0x9abcdef0i64).unwrap(), &[0xce, 0x9a, 0xbc, 0xde, 0xf0]);
+        assert_eq!(::to_bytes(-0x123456789abcdefi64).unwrap(),
+                   &[0xd3, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32, 0x11]);
+        assert_eq!(::to_bytes(0x123456789abcdef0u64).unwrap(),
+                   &[0xcf, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]);
+    }
+
+    #[test]
+    fn big_endian_floats_test() {
+        assert_eq!(::to_bytes(1.5f32).unwrap(), &[0xca, 0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(::to_bytes(-2.75f64).unwrap(),
+                   &[0xcb, 0xc0, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn big_endian_lengths_test() {
+        let s = "a".repeat(0x1234);
+        assert_eq!(&::to_bytes(&s).unwrap()[..3], &[0xda, 0x12, 0x34]);
+
+        let s = "a".repeat(0x10203);
+        assert_eq!(&::to_bytes(&s).unwrap()[..5], &[0xdb, 0x00, 0x01, 0x02, 0x03]);
+
+        let bytes = ::std::ffi::CString::new(vec![1u8; 0x1234]).unwrap();
+        assert_eq!(&::to_bytes(&bytes).unwrap()[..3], &[0xc5, 0x12, 0x34]);
+
+        let bytes = ::std::ffi::CString::new(vec![1u8; 0x10203]).unwrap();
+        assert_eq!(&::to_bytes(&bytes).unwrap()[..5], &[0xc6, 0x00, 0x01, 0x02, 0x03]);
+
+        let array = vec![0u8; 0x10203];
+        assert_eq!(&::to_bytes(&array).unwrap()[..5], &[0xdd, 0x00, 0x01, 0x02, 0x03]);
+
+        let map: BTreeMap<u32, u8> = (0..0x1234).map(|i| (i, 0)).collect();
+        assert_eq!(&::to_bytes(&map).unwrap()[..3], &[0xde, 0x12, 0x34]);
+
+        let map: BTreeMap<u32, u8> = (0..0x10203).map(|i| (i, 0)).collect();
+        assert_eq!(&::to_bytes(&map).unwrap()[..5], &[0xdf, 0x00, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn legacy_raw_test() {
+        let config = SerializerConfig::new().legacy_raw(true);
+
+        assert_eq!(::to_bytes_with_config("ab", config).unwrap(), &[0xa2, 0x61, 0x62]);
+
+        let bytes = ::std::ffi::CString::new(vec![1u8, 2]).unwrap();
+        assert_eq!(::to_bytes_with_config(&bytes, config).unwrap(), &[0xa2, 0x01, 0x02]);
+
+        // there was no raw8, so anything past a fixraw goes straight to raw16
+        let s = "a".repeat(0x40);
+        assert_eq!(&::to_bytes_with_config(&s, config).unwrap()[..3], &[0xda, 0x00, 0x40]);
+
+        let bytes = ::std::ffi::CString::new(vec![1u8; 0x40]).unwrap();
+        assert_eq!(&::to_bytes_with_config(&bytes, config).unwrap()[..3], &[0xda, 0x00, 0x40]);
+
+        let bytes = ::std::ffi::CString::new(vec![1u8; 0x10203]).unwrap();
+        assert_eq!(&::to_bytes_with_config(&bytes, config).unwrap()[..5],
+                   &[0xdb, 0x00, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn map_key_value_order_test() {
+        use serde::ser::SerializeMap;
+
+        let mut ser = ::Serializer::new(|_: &[u8]| Ok(()));
+        let mut map = (&mut ser).serialize_map(Some(2)).unwrap();
+
+        map.serialize_key("a").unwrap();
+        match map.serialize_key("b") {
+            Err(Error::Other(ref message)) => assert!(message.contains("value was expected")),
+            other => panic!("expected an ordering error, got {:?}", other),
+        }
+
+        let mut ser = ::Serializer::new(|_: &[u8]| Ok(()));
+        let mut map = (&mut ser).serialize_map(None).unwrap();
+
+        assert!(map.serialize_value(&1).is_err());
+    }
+
+    #[test]
+    fn canonical_test() {
+        use std::collections::HashMap;
+
+        #[derive(Serialize)]
+        struct Signed {
+            zeta: u32,
+            alpha: HashMap<String, i64>,
+        }
+
+        let encode = |value: &Signed| {
+            let mut bytes = vec![];
+            {
+                let mut ser = ::Serializer::canonical(|buf: &[u8]| {
+                    bytes.extend_from_slice(buf);
+                    Ok(())
+                });
+                value.serialize(&mut ser).unwrap();
+            }
+            bytes
+        };
+
+        let mut first = HashMap::new();
+        let mut second = HashMap::new();
+        for i in 0..20 {
+            first.insert(format!("key{}", i), i);
+            second.insert(format!("key{}", 19 - i), 19 - i);
+        }
+
+        let first = encode(&Signed { zeta: 1, alpha: first });
+        let second = encode(&Signed { zeta: 1, alpha: second });
+
+        assert_eq!(first, second);
+
+        // fields are sorted by their encoded bytes too, where the string header puts shorter
+        // names first
+        assert_eq!(&first[..8], &[0x82, 0xa4, 0x7a, 0x65, 0x74, 0x61, 0x01, 0xa5]);
+
+        assert!(::to_bytes_with_config(f64::NAN, SerializerConfig::canonical()).is_err());
+    }
+
+    #[test]
+    fn canonical_duplicate_keys_test() {
+        struct Twice;
+
+        impl Serialize for Twice {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeMap;
+
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("a", &1)?;
+                map.serialize_entry("a", &2)?;
+                map.end()
+            }
+        }
+
+        assert!(::to_bytes(Twice).is_ok());
+
+        match ::to_bytes_with_config(Twice, SerializerConfig::canonical()) {
+            Err(Error::InvalidKey) => {}
+            other => panic!("expected InvalidKey, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assume_sorted_keys_test() {
+        let config = SerializerConfig::canonical().assume_sorted_keys(true);
+
+        let encode = |value: &BTreeMap<u8, f64>| {
+            let mut bytes = vec![];
+            let result = {
+                let mut ser = ::Serializer::with_config(|buf: &[u8]| {
+                                                            bytes.extend_from_slice(buf);
+                                                            Ok(())
+                                                        },
+                                                        config);
+                value.serialize(&mut ser)
+            };
+            (result, bytes)
+        };
+
+        let mut map = BTreeMap::new();
+        map.insert(2, 0.5);
+        map.insert(1, 1.5);
+
+        let (result, bytes) = encode(&map);
+        result.unwrap();
+        assert_eq!(bytes, ::to_bytes_with_config(&map, SerializerConfig::canonical()).unwrap());
+
+        // entries reach the output as they're written, so the ones before a failure are there
+        map.insert(3, f64::NAN);
+        let (result, bytes) = encode(&map);
+        match result {
+            Err(Error::NonFinite) => {}
+            other => panic!("expected NonFinite, got {:?}", other),
+        }
+        assert_eq!(&bytes[..5], &[0x83, 0x01, 0xcb, 0x3f, 0xf8]);
+        assert_eq!(&bytes[11..13], &[0x02, 0xcb]);
+        assert_eq!(&bytes[21..], &[0x03]);
+    }
+
+    #[test]
+    fn assume_sorted_keys_out_of_order_test() {
+        struct Descending;
+
+        impl Serialize for Descending {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_map((0..3u8).rev().map(|key| (key, ())))
+            }
+        }
+
+        let config = SerializerConfig::canonical().assume_sorted_keys(true);
+        match ::to_bytes_with_config(Descending, config) {
+            Err(Error::InvalidKey) => {}
+            other => panic!("expected InvalidKey, got {:?}", other),
+        }
+
+        // without the assumption, the entries are sorted instead
+        assert_eq!(::to_bytes_with_config(Descending, SerializerConfig::canonical()).unwrap(),
+                   &[0x83, 0x00, 0xc0, 0x01, 0xc0, 0x02, 0xc0]);
+    }
+
+    #[test]
+    fn buffered_test() {
+        let values: Vec<u32> = (0..1000).collect();
+
+        let mut writes = 0;
+        let mut bytes = vec![];
+        {
+            let mut ser = ::Serializer::buffered(|buf: &[u8]| {
+                                                     writes += 1;
+                                                     bytes.extend_from_slice(buf);
+                                                     Ok(())
+                                                 },
+                                                 256);
+            values.serialize(&mut ser).unwrap();
+            ser.flush().unwrap();
+        }
+
+        assert_eq!(bytes, ::to_bytes(&values).unwrap());
+        assert!(writes <= bytes.len() / 256 + 1);
+
+        // without buffering, every integer is written separately
+        let mut unbuffered = 0;
+        {
+            let mut ser = ::Serializer::new(|_: &[u8]| {
+                                                unbuffered += 1;
+                                                Ok(())
+                                            });
+            values.serialize(&mut ser).unwrap();
+        }
+        assert!(unbuffered > 1000);
+    }
+
+    #[test]
+    fn buffered_patch_test() {
+        use write::{BufferedWrite, VecWrite};
+
+        let values: Vec<u32> = (0..100).collect();
+        let config = SerializerConfig::new().backpatch_sequences(true);
+
+        let mut bytes = vec![];
+        {
+            let output = BufferedWrite::new(VecWrite::new(&mut bytes), 16);
+            let mut ser = ::Serializer::from_write_with_config(output, config);
+
+            // a sequence of unknown length, so its header is patched in at the end
+            ser.collect_seq(values.iter().filter(|_| true)).unwrap();
+            ser.into_inner().into_inner().unwrap();
+        }
+
+        // the header was flushed long before the length was known
+        assert_eq!(&bytes[..5], &[0xdd, 0x00, 0x00, 0x00, 0x64]);
+        assert_eq!(::from_bytes::<Vec<u32>>(&bytes).unwrap(), values);
+    }
+
+    #[test]
+    fn buffered_drop_test() {
+        use write::{BufferedWrite, VecWrite, Write};
+
+        // bytes still buffered are written out when the output is dropped
+        let mut bytes = vec![];
+        {
+            let mut ser = ::Serializer::buffered(VecWrite::new(&mut bytes), 16);
+            "abc".serialize(&mut ser).unwrap();
+        }
+        assert_eq!(bytes, &[0xa3, 0x61, 0x62, 0x63]);
+
+        // patching needs an underlying output that can be patched, and fails the same way
+        // whether or not it's buffered
+        let mut plain = |_: &[u8]| Ok(());
+        let expected = match plain.patch(0, &[0x91]) {
+            Err(Error::Other(message)) => message,
+            other => panic!("expected Other, got {:?}", other),
+        };
+
+        let mut output = BufferedWrite::new(plain, 16);
+        output.write(&[0x90]).unwrap();
+        match output.patch(0, &[0x91]) {
+            Err(Error::Other(ref message)) if *message == expected => {}
+            other => panic!("expected Other({:?}), got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn skip_none_fields_test() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Sparse {
+            a: u8,
+            b: Option<u8>,
+            c: Option<u8>,
+        }
+
+        let value = Sparse { a: 1, b: None, c: Some(2) };
+        let expected = [0x82, 0xa1, 0x61, 0x01, 0xa1, 0x63, 0x92, 0xc3, 0x02];
+
+        // an append-only output buffers the struct to write the minimal header
+        let config = SerializerConfig::new().skip_none_fields(true);
+        let mut bytes = vec![];
+        {
+            let mut ser = ::Serializer::with_config(|buf: &[u8]| {
+                                                        bytes.extend_from_slice(buf);
+                                                        Ok(())
+                                                    },
+                                                    config);
+            value.serialize(&mut ser).unwrap();
+        }
+        assert_eq!(bytes, &expected);
+        assert_eq!(::from_bytes::<Sparse>(&bytes).unwrap(), value);
+
+        // a patchable output gets a placeholder header with the real count filled in
+        let bytes = ::to_bytes_with_config(&value, config.backpatch_sequences(true)).unwrap();
+        assert_eq!(&bytes[..5], &[0xdf, 0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(&bytes[5..], &expected[1..]);
+        assert_eq!(::from_bytes::<Sparse>(&bytes).unwrap(), value);
+
+        // positional structs keep every field
+        let bytes = ::to_bytes_with_config(&value, config.struct_as_array(true)).unwrap();
+        assert_eq!(bytes, &[0x93, 0x01, 0x91, 0xc2, 0x92, 0xc3, 0x02]);
+    }
+
+    #[test]
+    fn ext_header_test() {
+        use read::BorrowRead;
+        use Deserializer;
+
+        let payload: Vec<u8> = (0..100).collect();
+
+        let mut bytes = vec![];
+        {
+            let mut ser = ::Serializer::new(|buf: &[u8]| {
+                                                bytes.extend_from_slice(buf);
+                                                Ok(())
+                                            });
+
+            ser.write_ext_header(5, payload.len()).unwrap();
+            for chunk in payload.chunks(30) {
+                ser.write_raw(chunk).unwrap();
+            }
+
+            ser.write_ext_header(-2, 4).unwrap();
+            ser.write_raw(&[1, 2, 3, 4]).unwrap();
+        }
+
+        assert_eq!(&bytes[..3], &[0xc7, 0x64, 0x05]);
+
+        let mut position = 0;
+        let mut de = Deserializer::new(BorrowRead::new(|len: usize| {
+            let result = &bytes[position..position + len];
+            position += len;
+            Ok(result)
+        }));
+
+        assert_eq!(de.read_ext().unwrap(), (5, payload));
+        assert_eq!(de.read_ext().unwrap(), (-2, vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn unit_struct_test() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        struct Marker;
+
+        let bytes = ::to_bytes(Marker).unwrap();
+        assert_eq!(bytes, &[0xc0]);
+        assert_eq!(::from_bytes::<Marker>(&bytes).unwrap(), Marker);
+
+        let config = SerializerConfig::new().unit_struct_as_empty_map(true);
+        let bytes = ::to_bytes_with_config(Marker, config).unwrap();
+        assert_eq!(bytes, &[0x80]);
+        assert_eq!(::from_bytes::<Marker>(&bytes).unwrap(), Marker);
+
+        assert!(::from_bytes::<Marker>(&[0x81, 0xc0, 0xc0]).is_err());
+    }
+
+    #[test]
+    fn borrowed_cow_test() {
+        use std::borrow::Cow;
+
+        let text: Cow<str> = Cow::Borrowed("x");
+        assert_eq!(::to_bytes(&text).unwrap(), ::to_bytes("x").unwrap());
+
+        let data: Cow<[u8]> = Cow::Borrowed(&[1, 2, 3]);
+        assert_eq!(::to_bytes(&data).unwrap(), ::to_bytes(&[1u8, 2, 3][..]).unwrap());
+
+        // the borrowed data goes straight to the output, so a fixed buffer is enough
+        let mut buf = [0u8; 8];
+        let mut len = 0;
+        {
+            let mut ser = ::Serializer::new(|bytes: &[u8]| {
+                buf[len..len + bytes.len()].copy_from_slice(bytes);
+                len += bytes.len();
+                Ok(())
+            });
+            (text, data).serialize(&mut ser).unwrap();
+        }
+
+        assert_eq!(&buf[..len], &[0x92, 0xa1, 0x78, 0x93, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn unpaired_key_test() {
+        use serde::ser::SerializeMap;
+
+        // a map that ends after a key, without its value
+        struct Unpaired(Option<usize>);
+
+        impl Serialize for Unpaired {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                let mut map = serializer.serialize_map(self.0)?;
+                map.serialize_entry("a", &1)?;
+                map.serialize_key("b")?;
+                map.end()
+            }
+        }
+
+        for size in &[None, Some(2)] {
+            match ::to_bytes(Unpaired(*size)) {
+                Err(Error::BadLength) => {}
+                other => panic!("expected BadLength for {:?}, got {:?}", size, other),
+            }
+        }
+    }
 }
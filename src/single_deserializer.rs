@@ -0,0 +1,73 @@
+//! The visitor that presents a lone value as a one-element sequence.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+use serde::de::{SeqAccess, DeserializeSeed, Visitor};
+
+use de::Deserializer;
+
+use error::Error;
+use read::Read;
+
+pub struct SingleDeserializer<'de: 'a, 'a, R: 'a + Read<'de>> {
+    de: &'a mut Deserializer<'de, R>,
+    ty: Option<u8>,
+}
+
+/// Decodes a value whose type byte has already been read.
+pub struct PendingDeserializer<'de: 'a, 'a, R: 'a + Read<'de>> {
+    de: &'a mut Deserializer<'de, R>,
+    ty: u8,
+}
+
+impl<'de, 'a, R: Read<'de>> SingleDeserializer<'de, 'a, R> {
+    pub fn new(de: &'a mut Deserializer<'de, R>, ty: u8) -> SingleDeserializer<'de, 'a, R> {
+        SingleDeserializer { de, ty: Some(ty) }
+    }
+}
+
+impl<'de, 'a, R: Read<'de>> PendingDeserializer<'de, 'a, R> {
+    pub fn new(de: &'a mut Deserializer<'de, R>, ty: u8) -> PendingDeserializer<'de, 'a, R> {
+        PendingDeserializer { de, ty }
+    }
+}
+
+impl<'de, 'a, R: Read<'de>> SeqAccess<'de> for SingleDeserializer<'de, 'a, R> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+        where T: DeserializeSeed<'de>
+    {
+        match self.ty.take() {
+            Some(ty) => {
+                seed.deserialize(PendingDeserializer {
+                                     de: &mut *self.de,
+                                     ty,
+                                 })
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(if self.ty.is_some() { 1 } else { 0 })
+    }
+}
+
+impl<'de, 'a, R: Read<'de>> ::serde::Deserializer<'de> for PendingDeserializer<'de, 'a, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+        where V: Visitor<'de>
+    {
+        self.de.parse_as(visitor, self.ty)
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
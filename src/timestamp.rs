@@ -0,0 +1,202 @@
+//! Helpers for the MessagePack timestamp extension type.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::String;
+
+use std::fmt;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use serde::de::{self, Deserialize, Deserializer, Visitor, MapAccess};
+
+use defs::*;
+
+/// The extension type reserved for timestamps.
+pub const TIMESTAMP_TYPE: i8 = -1;
+
+/// A point in time decoded from a timestamp extension.
+///
+/// The deserializer presents timestamp extensions as a map with `seconds` and `nanoseconds`
+/// entries instead of the raw extension bytes, which is what this type decodes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch.
+    pub seconds: i64,
+
+    /// Nanoseconds within the second.
+    pub nanoseconds: u32,
+}
+
+impl Timestamp {
+    /// Fractional seconds since the Unix epoch.
+    pub fn as_f64(&self) -> f64 {
+        self.seconds as f64 + self.nanoseconds as f64 / 1e9
+    }
+}
+
+/// Decode a timestamp extension as fractional seconds since the Unix epoch.
+///
+/// Accepts the 32, 64 and 96-bit timestamp encodings. Use with
+/// `#[serde(deserialize_with = "corepack::timestamp::timestamp_as_f64")]`.
+pub fn timestamp_as_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
+    where D: Deserializer<'de>
+{
+    Timestamp::deserialize(deserializer).map(|timestamp| timestamp.as_f64())
+}
+
+/// The largest nanoseconds value a timestamp may carry.
+const MAX_NANOSECONDS: u32 = 999_999_999;
+
+/// Split the payload of an extension into seconds and nanoseconds, if it is a timestamp.
+///
+/// A payload whose nanoseconds are past `MAX_NANOSECONDS` isn't a valid timestamp, so it is left
+/// to come through as a plain extension.
+pub(crate) fn decode(ty: i8, data: &[u8]) -> Option<(i64, u32)> {
+    if ty != TIMESTAMP_TYPE {
+        return None;
+    }
+
+    let (seconds, nanoseconds) = match data.len() {
+        U32_BYTES => (BigEndian::read_u32(data) as i64, 0),
+        U64_BYTES => {
+            let value = BigEndian::read_u64(data);
+            ((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32)
+        }
+        TIMESTAMP96_BYTES => (BigEndian::read_i64(&data[U32_BYTES..]), BigEndian::read_u32(data)),
+        _ => return None,
+    };
+
+    if nanoseconds > MAX_NANOSECONDS {
+        return None;
+    }
+
+    Some((seconds, nanoseconds))
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Timestamp, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_map(TimestampVisitor)
+    }
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Timestamp;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a timestamp extension")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Timestamp, A::Error>
+        where A: MapAccess<'de>
+    {
+        let mut seconds = None;
+        let mut nanoseconds = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match &*key {
+                "seconds" => seconds = Some(map.next_value()?),
+                "nanoseconds" => nanoseconds = Some(map.next_value()?),
+                _ => return Err(de::Error::unknown_field(&key, &["seconds", "nanoseconds"])),
+            }
+        }
+
+        Ok(Timestamp {
+            seconds: seconds.ok_or_else(|| de::Error::missing_field("seconds"))?,
+            nanoseconds: nanoseconds.ok_or_else(|| de::Error::missing_field("nanoseconds"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::Timestamp;
+
+    #[derive(Deserialize)]
+    struct Event {
+        #[serde(deserialize_with = "super::timestamp_as_f64")]
+        at: f64,
+    }
+
+    fn decode(ext: &[u8]) -> f64 {
+        // a map with a single entry 'at'
+        let mut fixture = vec![0x81, 0xa2, 0x61, 0x74];
+        fixture.extend_from_slice(ext);
+
+        ::from_bytes::<Event>(&fixture).unwrap().at
+    }
+
+    #[test]
+    fn timestamp32_test() {
+        assert_eq!(decode(&[0xd6, 0xff, 0x59, 0x68, 0x2f, 0x00]), 1500000000.0);
+    }
+
+    #[test]
+    fn timestamp64_test() {
+        assert_eq!(decode(&[0xd7, 0xff, 0x00, 0x00, 0x00, 0x00, 0x59, 0x68, 0x2f, 0x00]),
+                   1500000000.0);
+
+        // 500000000 nanoseconds in the upper 30 bits
+        assert_eq!(decode(&[0xd7, 0xff, 0x77, 0x35, 0x94, 0x00, 0x59, 0x68, 0x2f, 0x00]),
+                   1500000000.5);
+    }
+
+    #[test]
+    fn timestamp96_test() {
+        assert_eq!(decode(&[0xc7, 0x0c, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                            0x59, 0x68, 0x2f, 0x00]),
+                   1500000000.0);
+
+        assert_eq!(decode(&[0xc7, 0x0c, 0xff, 0x1d, 0xcd, 0x65, 0x00, 0xff, 0xff, 0xff, 0xff,
+                            0xff, 0xff, 0xff, 0xfe]),
+                   -1.5);
+    }
+
+    #[test]
+    fn nanoseconds_range_test() {
+        // one second's worth of nanoseconds, in the 64-bit form
+        let fixture = [0xd7, 0xff, 0xee, 0x6b, 0x28, 0x00, 0x59, 0x68, 0x2f, 0x00];
+        assert!(::from_bytes::<Timestamp>(&fixture).is_err());
+
+        // and in the 96-bit form
+        let fixture = [0xc7, 0x0c, 0xff, 0x3b, 0x9a, 0xca, 0x00, 0x00, 0x00, 0x00, 0x00, 0x59,
+                       0x68, 0x2f, 0x00];
+        assert!(::from_bytes::<Timestamp>(&fixture).is_err());
+
+        // the largest valid value still decodes
+        let fixture = [0xc7, 0x0c, 0xff, 0x3b, 0x9a, 0xc9, 0xff, 0x00, 0x00, 0x00, 0x00, 0x59,
+                       0x68, 0x2f, 0x00];
+        assert_eq!(::from_bytes::<Timestamp>(&fixture).unwrap().nanoseconds, 999_999_999);
+    }
+
+    #[test]
+    fn wrong_type_test() {
+        let fixture = [0x81, 0xa2, 0x61, 0x74, 0xd6, 0x01, 0x59, 0x68, 0x2f, 0x00];
+        assert!(::from_bytes::<Event>(&fixture).is_err());
+    }
+
+    #[test]
+    fn deserialize_any_test() {
+        let fixture = [0xd7, 0xff, 0x77, 0x35, 0x94, 0x00, 0x59, 0x68, 0x2f, 0x00];
+
+        let timestamp: Timestamp = ::from_bytes(&fixture).unwrap();
+        assert_eq!(timestamp,
+                   Timestamp {
+                       seconds: 1500000000,
+                       nanoseconds: 500000000,
+                   });
+
+        // self-describing targets see the structured timestamp, not the extension bytes
+        let map: BTreeMap<String, i64> = ::from_bytes(&fixture).unwrap();
+        assert_eq!(map.get("seconds"), Some(&1500000000));
+        assert_eq!(map.get("nanoseconds"), Some(&500000000));
+    }
+}
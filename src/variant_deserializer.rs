@@ -3,20 +3,20 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License,
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
-#[cfg(feature = "alloc")]
-use alloc::borrow::ToOwned;
-
 use serde::de::{IntoDeserializer, DeserializeSeed, EnumAccess, Visitor, Deserialize, VariantAccess};
-use serde::de::value::StringDeserializer;
+use serde::de::value::StrDeserializer;
 
 use de::Deserializer;
 
+use defs::*;
 use error::Error;
 use read::Read;
+use single_deserializer::PendingDeserializer;
 
 pub struct VariantDeserializer<'de: 'a, 'a, R: 'a + Read<'de>> {
     de: &'a mut Deserializer<'de, R>,
     variants: &'static [&'static str],
+    bare: bool,
 }
 
 impl<'de, 'a, R: Read<'de>> VariantDeserializer<'de, 'a, R> {
@@ -24,8 +24,18 @@ impl<'de, 'a, R: Read<'de>> VariantDeserializer<'de, 'a, R> {
                variants: &'static [&'static str])
                -> VariantDeserializer<'de, 'a, R> {
         VariantDeserializer {
-            de: de,
-            variants: variants,
+            de,
+            variants,
+            bare: false,
+        }
+    }
+
+    fn check_not_bare(&self) -> Result<(), Error> {
+        // only unit variants can be encoded without a value
+        if self.bare {
+            Err(Error::BadType)
+        } else {
+            Ok(())
         }
     }
 }
@@ -34,20 +44,32 @@ impl<'de, 'a, R: Read<'de>> EnumAccess<'de> for VariantDeserializer<'de, 'a, R>
     type Error = Error;
     type Variant = VariantDeserializer<'de, 'a, R>;
 
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Error>
+    fn variant_seed<V>(mut self, seed: V) -> Result<(V::Value, Self::Variant), Error>
         where V: DeserializeSeed<'de>
     {
-        // get the variant index with a one-item tuple
-        let variant_index_container: (usize, /* enum-type */) =
-            Deserialize::deserialize(&mut *self.de)?;
+        // variants are either a two-tuple of the index and the value, or just the index or name
+        // for unit variants serialized with unit_variant_as_uint or unit_variant_as_str
+        let ty = self.de.read_type()?;
 
-        // the other value in this tuple would be the actual value of the enum,
-        // but we don't know what that is
-        let (variant_index /* enum-value */,) = variant_index_container;
+        if FIXSTR.contains(ty) || ty == STR8 || ty == STR16 || ty == STR32 {
+            self.bare = true;
+            let value = seed.deserialize(PendingDeserializer::new(&mut *self.de, ty))?;
+
+            return Ok((value, self));
+        }
+
+        let variant_index = match self.de.parse_integer::<usize>(ty)? {
+            Some(variant_index) => {
+                self.bare = true;
+                variant_index
+            }
+            None if ty == 2 | FIXARRAY_MASK => Deserialize::deserialize(&mut *self.de)?,
+            None => return Err(Error::BadType),
+        };
 
         // translate that to the name of the variant
-        let name = self.variants[variant_index].to_owned();
-        let de: StringDeserializer<Error> = name.into_deserializer();
+        let name: &'static str = self.variants.get(variant_index).ok_or(Error::BadType)?;
+        let de: StrDeserializer<Error> = name.into_deserializer();
         let value = seed.deserialize(de)?;
 
         Ok((value, self))
@@ -60,22 +82,29 @@ impl<'de, 'a, R: Read<'de>> VariantAccess<'de> for VariantDeserializer<'de, 'a,
     fn tuple_variant<V>(self, _: usize, visitor: V) -> Result<V::Value, Error>
         where V: Visitor<'de>
     {
+        self.check_not_bare()?;
         ::serde::Deserializer::deserialize_any(self.de, visitor)
     }
 
     fn struct_variant<V>(self, _: &'static [&'static str], visitor: V) -> Result<V::Value, Error>
         where V: Visitor<'de>
     {
+        self.check_not_bare()?;
         ::serde::Deserializer::deserialize_any(self.de, visitor)
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
         where T: DeserializeSeed<'de>
     {
+        self.check_not_bare()?;
         seed.deserialize(self.de)
     }
 
     fn unit_variant(self) -> Result<(), Error> {
-        Deserialize::deserialize(&mut *self.de)
+        if self.bare {
+            Ok(())
+        } else {
+            Deserialize::deserialize(&mut *self.de)
+        }
     }
 }
@@ -0,0 +1,363 @@
+//! Helpers for picking the wire representation of a field with `#[serde(with = "...")]`.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserializer, Visitor, SeqAccess};
+
+/// Accepts byte arrays, and arrays of integers, as a byte vector.
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a byte array")
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Vec<u8>, E>
+        where E: de::Error
+    {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E>
+        where E: de::Error
+    {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Vec<u8>, A::Error>
+        where A: SeqAccess<'de>
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn deserialize_bytes<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where T: TryFrom<Vec<u8>>,
+          D: Deserializer<'de>
+{
+    let bytes = deserializer.deserialize_byte_buf(ByteBufVisitor)?;
+    let len = bytes.len();
+
+    T::try_from(bytes).map_err(|_| de::Error::invalid_length(len, &"a byte array of that length"))
+}
+
+/// Write a byte vector or array as bin, rather than as an array of integers.
+///
+/// Works with any field that is `AsRef<[u8]>` and `TryFrom<Vec<u8>>`, such as `Vec<u8>` and
+/// `[u8; N]`. Decoding also accepts an array of integers.
+pub mod as_bin {
+    #[cfg(feature = "alloc")]
+    use alloc::Vec;
+
+    use std::convert::TryFrom;
+
+    use serde::{Serializer, Deserializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: ?Sized + AsRef<[u8]>,
+              S: Serializer
+    {
+        serializer.serialize_bytes(value.as_ref())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+        where T: TryFrom<Vec<u8>>,
+              D: Deserializer<'de>
+    {
+        super::deserialize_bytes(deserializer)
+    }
+}
+
+/// Write a byte vector or array as an array of integers, rather than as bin.
+///
+/// Works with any field that is `AsRef<[u8]>` and `TryFrom<Vec<u8>>`, such as `Vec<u8>` and
+/// `[u8; N]`. Decoding also accepts bin.
+pub mod as_array {
+    #[cfg(feature = "alloc")]
+    use alloc::Vec;
+
+    use std::convert::TryFrom;
+
+    use serde::{Serializer, Deserializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: ?Sized + AsRef<[u8]>,
+              S: Serializer
+    {
+        serializer.collect_seq(value.as_ref())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+        where T: TryFrom<Vec<u8>>,
+              D: Deserializer<'de>
+    {
+        super::deserialize_bytes(deserializer)
+    }
+}
+
+/// Write an array or vector of chars, such as `[char; N]`, as a string, rather than as an array
+/// of one-character strings.
+///
+/// Decoding fails if the string doesn't hold exactly as many chars as the field does. It also
+/// accepts an array of chars.
+pub mod chars_as_str {
+    #[cfg(feature = "alloc")]
+    use alloc::{String, Vec};
+
+    use std::convert::TryFrom;
+
+    use serde::{Serializer, Deserializer};
+    use serde::de::Error;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: ?Sized + AsRef<[char]>,
+              S: Serializer
+    {
+        serializer.serialize_str(&value.as_ref().iter().collect::<String>())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+        where T: TryFrom<Vec<char>>,
+              D: Deserializer<'de>
+    {
+        let chars = deserializer.deserialize_str(super::CharsVisitor)?;
+        let len = chars.len();
+
+        T::try_from(chars)
+            .map_err(|_| D::Error::invalid_length(len, &"a string of that many chars"))
+    }
+}
+
+/// Write a sequence of key-value pairs, such as a `Vec<(K, V)>`, as a map.
+pub mod as_map {
+    use std::iter::FromIterator;
+    use std::marker::PhantomData;
+
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<T, K, V, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where for<'a> &'a T: IntoIterator<Item = &'a (K, V)>,
+              K: Serialize,
+              V: Serialize,
+              S: Serializer
+    {
+        serializer.collect_map(value.into_iter().map(|(key, value)| (key, value)))
+    }
+
+    pub fn deserialize<'de, T, K, V, D>(deserializer: D) -> Result<T, D::Error>
+        where T: FromIterator<(K, V)>,
+              K: Deserialize<'de>,
+              V: Deserialize<'de>,
+              D: Deserializer<'de>
+    {
+        deserializer.deserialize_map(super::PairsVisitor(PhantomData))
+    }
+}
+
+/// Write a `Duration` as a two-element array of whole seconds and nanoseconds, rather than as a
+/// map with `secs` and `nanos` entries.
+pub mod duration_as_array {
+    use std::time::Duration;
+
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::{Error, Unexpected};
+
+    const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+    pub fn serialize<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        (value.as_secs(), value.subsec_nanos()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+        where D: Deserializer<'de>
+    {
+        let (secs, nanos) = <(u64, u32)>::deserialize(deserializer)?;
+
+        // a whole second's worth of nanoseconds could overflow the seconds
+        if nanos >= NANOS_PER_SEC {
+            return Err(D::Error::invalid_value(Unexpected::Unsigned(nanos as u64),
+                                               &"fewer than 1000000000 nanoseconds"));
+        }
+
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+/// Accepts strings, and arrays of chars, as a vector of chars.
+struct CharsVisitor;
+
+impl<'de> Visitor<'de> for CharsVisitor {
+    type Value = Vec<char>;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a string")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Vec<char>, E>
+        where E: de::Error
+    {
+        Ok(value.chars().collect())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Vec<char>, A::Error>
+        where A: SeqAccess<'de>
+    {
+        let mut chars = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(c) = seq.next_element()? {
+            chars.push(c);
+        }
+
+        Ok(chars)
+    }
+}
+
+/// Collects the entries of a map into any collection of pairs.
+struct PairsVisitor<T, K, V>(PhantomData<(T, K, V)>);
+
+impl<'de, T, K, V> Visitor<'de> for PairsVisitor<T, K, V>
+    where T: ::std::iter::FromIterator<(K, V)>,
+          K: de::Deserialize<'de>,
+          V: de::Deserialize<'de>
+{
+    type Value = T;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<T, A::Error>
+        where A: de::MapAccess<'de>
+    {
+        let mut pairs = Vec::with_capacity(map.size_hint().unwrap_or(0));
+
+        while let Some(pair) = map.next_entry()? {
+            pairs.push(pair);
+        }
+
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Fields {
+        #[serde(with = "super::as_bin")]
+        digest: [u8; 4],
+        #[serde(with = "super::as_array")]
+        raw: Vec<u8>,
+        #[serde(with = "super::as_map")]
+        pairs: Vec<(String, u8)>,
+    }
+
+    #[test]
+    fn with_test() {
+        let fields = Fields {
+            digest: [1, 2, 3, 4],
+            raw: vec![5, 6],
+            pairs: vec![("b".into(), 7), ("a".into(), 8)],
+        };
+
+        let bytes = ::to_bytes(&fields).unwrap();
+
+        assert_eq!(bytes,
+                   &[0x83, 0xa6, 0x64, 0x69, 0x67, 0x65, 0x73, 0x74, 0xc4, 0x04, 0x01, 0x02,
+                     0x03, 0x04, 0xa3, 0x72, 0x61, 0x77, 0x92, 0x05, 0x06, 0xa5, 0x70, 0x61,
+                     0x69, 0x72, 0x73, 0x82, 0xa1, 0x62, 0x07, 0xa1, 0x61, 0x08]);
+
+        assert_eq!(::from_bytes::<Fields>(&bytes).unwrap(), fields);
+    }
+
+    #[test]
+    fn duration_as_array_test() {
+        use std::time::Duration;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Timing {
+            #[serde(with = "super::duration_as_array")]
+            elapsed: Duration,
+        }
+
+        let round_trip = |elapsed: Duration| {
+            let bytes = ::to_bytes(Timing { elapsed }).unwrap();
+            assert_eq!(::from_bytes::<Timing>(&bytes).unwrap().elapsed, elapsed);
+            bytes
+        };
+
+        assert_eq!(round_trip(Duration::from_millis(250)),
+                   &[0x81, 0xa7, 0x65, 0x6c, 0x61, 0x70, 0x73, 0x65, 0x64, 0x92, 0x00, 0xce,
+                     0x0e, 0xe6, 0xb2, 0x80]);
+
+        // three days and a bit
+        round_trip(Duration::new(3 * 24 * 60 * 60, 1));
+        round_trip(Duration::new(u64::MAX, 999_999_999));
+
+        let bytes = ::to_bytes((u64::MAX, 1_000_000_000u32)).unwrap();
+        assert!(::from_bytes::<Timing>(&[&[0x81, 0xa7][..], b"elapsed", &bytes].concat())
+                    .is_err());
+    }
+
+    #[test]
+    fn chars_as_str_test() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Code {
+            #[serde(with = "super::chars_as_str")]
+            code: [char; 3],
+        }
+
+        let value = Code { code: ['a', 'b', 'c'] };
+        let bytes = ::to_bytes(&value).unwrap();
+        assert_eq!(&bytes[6..], &[0xa3, 0x61, 0x62, 0x63]);
+        assert_eq!(::from_bytes::<Code>(&bytes).unwrap(), value);
+
+        // multi-byte chars count once each
+        let value = Code { code: ['é', 'ß', '😀'] };
+        let bytes = ::to_bytes(&value).unwrap();
+        assert_eq!(::from_bytes::<Code>(&bytes).unwrap(), value);
+
+        #[derive(Serialize)]
+        struct PlainCode {
+            code: [char; 3],
+        }
+
+        // the plain array form is still accepted
+        let plain = ::to_bytes(PlainCode { code: ['x', 'y', 'z'] }).unwrap();
+        assert_eq!(::from_bytes::<Code>(&plain).unwrap(), Code { code: ['x', 'y', 'z'] });
+
+        let wrong = [0x81, 0xa4, 0x63, 0x6f, 0x64, 0x65, 0xa4, 0x61, 0x62, 0x63, 0x64];
+        assert!(::from_bytes::<Code>(&wrong).is_err());
+    }
+
+    #[test]
+    fn wrong_length_test() {
+        #[derive(Deserialize, Debug)]
+        struct Digest {
+            #[serde(with = "super::as_bin")]
+            #[allow(dead_code)]
+            digest: [u8; 4],
+        }
+
+        // a map with a three byte digest
+        let bytes = [0x81, 0xa6, 0x64, 0x69, 0x67, 0x65, 0x73, 0x74, 0xc4, 0x03, 0x01, 0x02, 0x03];
+        assert!(::from_bytes::<Digest>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! The write trait used by the serializer.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+
+use error::Error;
+
+/// The trait used by Serializer to write output data.
+///
+/// Any `FnMut(&[u8]) -> Result<(), Error>` closure is an append-only output. Outputs that also
+/// support going back and overwriting bytes they have already written can report their position,
+/// which lets the serializer fill in lengths once it knows them instead of buffering.
+pub trait Write: private::Sealed {
+    /// Writes all of the given bytes to the end of the output.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// The current length of the output, if previously written bytes can be overwritten.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+
+    /// Overwrites bytes at a position previously returned by `position`.
+    fn patch(&mut self, _position: usize, _bytes: &[u8]) -> Result<(), Error> {
+        Err(unpatchable())
+    }
+}
+
+/// The error for patching an output that doesn't support it.
+fn unpatchable() -> Error {
+    Error::Other("the output can't be patched".into())
+}
+
+/// Output that appends to a byte vector, and supports patching.
+pub struct VecWrite<'a> {
+    output: &'a mut Vec<u8>,
+}
+
+/// Output that writes to an `std::io` stream, and supports patching by seeking.
+#[cfg(feature = "std")]
+pub struct SeekWrite<W: ::std::io::Write + ::std::io::Seek> {
+    output: W,
+    position: usize,
+}
+
+/// Output that collects small writes into chunks before passing them on to another output.
+///
+/// Bytes reach the underlying output once at least `chunk_size` of them have built up. Whatever
+/// is left is written out when the BufferedWrite is dropped, but any error doing so is lost, so
+/// call `flush` or `into_inner` when done to find out whether it worked.
+pub struct BufferedWrite<W: Write> {
+    // only taken by into_inner, which consumes the BufferedWrite
+    output: Option<W>,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+/// Forwards to another output, so nested serializers keep its capabilities.
+pub(crate) struct Forward<'a, W: 'a + Write>(pub &'a mut W);
+
+impl<'a> VecWrite<'a> {
+    pub fn new(output: &'a mut Vec<u8>) -> VecWrite<'a> {
+        VecWrite { output }
+    }
+}
+
+impl<W: Write> BufferedWrite<W> {
+    /// Wrap an output, passing bytes on to it in chunks of at least `chunk_size` bytes.
+    pub fn new(output: W, chunk_size: usize) -> BufferedWrite<W> {
+        BufferedWrite {
+            output: Some(output),
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Write out any bytes that are still buffered.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.buffer.is_empty() {
+            if let Some(ref mut output) = self.output {
+                output.write(&self.buffer)?;
+            }
+
+            self.buffer.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Write out any bytes that are still buffered, and unwrap the underlying output.
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.flush()?;
+        self.output.take().ok_or_else(|| Error::Other("output already taken".into()))
+    }
+}
+
+impl<W: Write> Drop for BufferedWrite<W> {
+    fn drop(&mut self) {
+        // nowhere to report an error from here
+        let _ = self.flush();
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: ::std::io::Write + ::std::io::Seek> SeekWrite<W> {
+    /// Wrap a stream, treating its current position as the end of the output.
+    pub fn new(mut output: W) -> Result<SeekWrite<W>, Error> {
+        let position = output.stream_position()? as usize;
+
+        Ok(SeekWrite { output, position })
+    }
+
+    /// Unwrap the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> private::Sealed for F {}
+
+impl<'a> private::Sealed for VecWrite<'a> {}
+
+#[cfg(feature = "std")]
+impl<W: ::std::io::Write + ::std::io::Seek> private::Sealed for SeekWrite<W> {}
+
+impl<W: Write> private::Sealed for BufferedWrite<W> {}
+
+impl<'a, W: Write> private::Sealed for Forward<'a, W> {}
+
+impl<F: FnMut(&[u8]) -> Result<(), Error>> Write for F {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self(bytes)
+    }
+}
+
+impl<'a> Write for VecWrite<'a> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.output.len())
+    }
+
+    fn patch(&mut self, position: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.output[position..position + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: ::std::io::Write + ::std::io::Seek> Write for SeekWrite<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.output.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.position)
+    }
+
+    fn patch(&mut self, position: usize, bytes: &[u8]) -> Result<(), Error> {
+        use std::io::SeekFrom;
+
+        self.output.seek(SeekFrom::Start(position as u64))?;
+        self.output.write_all(bytes)?;
+        self.output.seek(SeekFrom::Start(self.position as u64))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BufferedWrite<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.buffer.extend_from_slice(bytes);
+
+        if self.buffer.len() >= self.chunk_size {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.output
+            .as_ref()
+            .and_then(Write::position)
+            .map(|position| position + self.buffer.len())
+    }
+
+    fn patch(&mut self, position: usize, bytes: &[u8]) -> Result<(), Error> {
+        let output = match self.output {
+            Some(ref mut output) => output,
+            None => return Err(Error::Other("output already taken".into())),
+        };
+        let flushed = output.position().ok_or_else(unpatchable)?;
+
+        // the bytes may be split between the underlying output and the buffer
+        if position < flushed {
+            let split = bytes.len().min(flushed - position);
+            output.patch(position, &bytes[..split])?;
+            self.buffer[..bytes.len() - split].copy_from_slice(&bytes[split..]);
+        } else {
+            let start = position - flushed;
+            self.buffer[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for Forward<'a, W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.0.write(bytes)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.0.position()
+    }
+
+    fn patch(&mut self, position: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.0.patch(position, bytes)
+    }
+}
+
+mod private {
+    /// Keeps users from directly implementing the Write trait
+    pub trait Sealed {}
+}
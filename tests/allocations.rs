@@ -0,0 +1,111 @@
+//! Checks on how many heap allocations decoding makes.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+//
+// These live in their own test binary, since counting needs a global allocator and that would
+// otherwise be installed for every unit test in the crate.
+extern crate corepack;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::io::BufReader;
+
+use serde::Deserializer as SerdeDeserializer;
+use serde::de::Visitor;
+
+use corepack::Deserializer;
+
+/// Wraps the system allocator to count the allocations made on each thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn scalar_struct_allocation_test() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Reading {
+        sensor: u8,
+        value: i32,
+        valid: bool,
+    }
+
+    // {"sensor": 3, "value": -70000, "valid": true}
+    let fixture = [0x83, 0xa6, 0x73, 0x65, 0x6e, 0x73, 0x6f, 0x72, 0x03, 0xa5, 0x76, 0x61, 0x6c,
+                   0x75, 0x65, 0xd2, 0xff, 0xfe, 0xee, 0x90, 0xa5, 0x76, 0x61, 0x6c, 0x69, 0x64,
+                   0xc3];
+
+    let before = allocations();
+    let reading: Reading = corepack::from_bytes(&fixture).unwrap();
+    assert_eq!(allocations(), before);
+
+    assert_eq!(reading,
+               Reading {
+                   sensor: 3,
+                   value: -70000,
+                   valid: true,
+               });
+
+    // the positional form doesn't allocate either
+    let before = allocations();
+    let reading: Reading = corepack::from_bytes(&[0x93, 0x03, 0x05, 0xc2]).unwrap();
+    assert_eq!(allocations(), before);
+    assert!(!reading.valid);
+
+    // while a string field does need one
+    let before = allocations();
+    let _: String = corepack::from_bytes(&[0xa1, 0x61]).unwrap();
+    assert_eq!(allocations(), before + 1);
+}
+
+#[test]
+fn buf_read_allocation_test() {
+    // measures a string without keeping it
+    struct Len;
+
+    impl<'de> Visitor<'de> for Len {
+        type Value = usize;
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("a string")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<usize, E> {
+            Ok(value.len())
+        }
+    }
+
+    let bytes = corepack::to_bytes("in buffer").unwrap();
+    let mut reader = BufReader::with_capacity(32, &bytes[..]);
+    let mut de = Deserializer::from_buf_read(&mut reader);
+
+    // a string that's already buffered is handed over without being copied
+    let before = allocations();
+    assert_eq!((&mut de).deserialize_str(Len).unwrap(), 9);
+    assert_eq!(allocations(), before);
+}